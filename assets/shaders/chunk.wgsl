@@ -0,0 +1,106 @@
+#import bevy_pbr::mesh_view_bindings
+#import bevy_pbr::mesh_bindings
+
+// NOTE: Bindings must come before functions that use them!
+#import bevy_pbr::mesh_functions
+
+@group(1) @binding(0)
+var atlas_texture: texture_2d<f32>;
+@group(1) @binding(1)
+var atlas_sampler: sampler;
+// Pixels with a lower alpha are discarded, for cutout blocks
+@group(1) @binding(2)
+var<uniform> alpha_cutoff: f32;
+
+// Size of a block texture in the atlas, in uv space
+let TILE_SIZE: f32 = 0.0625;
+let MAX_LIGHT: u32 = 15u;
+
+struct Vertex {
+    // Packed vertex, see `mesher::PackedVertex`
+    @location(0) voxel: u32,
+#ifdef FLUID_DROP
+    // Number of sixteenths of a block the vertex is lowered by, for the surface of fluids
+    @location(1) drop: u32,
+#endif
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_normal: vec3<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) tile: vec2<f32>,
+    @location(3) color: vec4<f32>,
+};
+
+// Normal of a face, in the order of `Face::ALL`
+fn face_normal(face: u32) -> vec3<f32> {
+    switch face {
+        case 0u: { return vec3<f32>(0.0, 1.0, 0.0); }
+        case 1u: { return vec3<f32>(0.0, -1.0, 0.0); }
+        case 2u: { return vec3<f32>(1.0, 0.0, 0.0); }
+        case 3u: { return vec3<f32>(-1.0, 0.0, 0.0); }
+        case 4u: { return vec3<f32>(0.0, 0.0, 1.0); }
+        default: { return vec3<f32>(0.0, 0.0, -1.0); }
+    }
+}
+
+// Coordinates inside of the tile, repeating every block, oriented the same way as `face_points`
+fn face_uv(face: u32, position: vec3<f32>) -> vec2<f32> {
+    switch face {
+        case 0u: { return vec2<f32>(position.z, -position.x); }
+        case 1u: { return vec2<f32>(-position.z, -position.x); }
+        case 2u: { return vec2<f32>(-position.z, -position.y); }
+        case 3u: { return vec2<f32>(-position.z, -position.y); }
+        default: { return vec2<f32>(position.x, -position.y); }
+    }
+}
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    let voxel = vertex.voxel;
+#ifdef FLUID_DROP
+    let lowered = f32(vertex.drop) / 16.0;
+#else
+    let lowered = 0.0;
+#endif
+    let position = vec3<f32>(f32(voxel & 31u), f32((voxel >> 5u) & 31u) - lowered, f32((voxel >> 10u) & 31u));
+    let face = (voxel >> 15u) & 7u;
+    let ao = (voxel >> 18u) & 3u;
+    let light = (voxel >> 20u) & 15u;
+
+    // Each light level is a bit darker than the one above it, and each occluding neighbour darkens the corner
+    let brightness = (0.4 + 0.2 * f32(ao)) * max(pow(0.8, f32(MAX_LIGHT - light)), 0.05);
+
+    var out: VertexOutput;
+    out.clip_position = mesh_position_local_to_clip(mesh.model, vec4<f32>(position, 1.0));
+    out.world_normal = mesh_normal_local_to_world(face_normal(face));
+    out.uv = face_uv(face, position);
+    out.tile = vec2<f32>(f32((voxel >> 24u) & 15u), f32((voxel >> 28u) & 15u));
+    out.color = vec4<f32>(brightness, brightness, brightness, 1.0);
+    return out;
+}
+
+struct FragmentInput {
+    @location(0) world_normal: vec3<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) tile: vec2<f32>,
+    @location(3) color: vec4<f32>,
+};
+
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    // Wrap the uvs inside of the tile so that merged faces repeat the texture
+    let uv = (in.tile + fract(in.uv)) * TILE_SIZE;
+    let color = textureSample(atlas_texture, atlas_sampler, uv);
+    if (color.a < alpha_cutoff) {
+        discard;
+    }
+
+    var diffuse = 0.0;
+    if (lights.n_directional_lights > 0u) {
+        diffuse = max(dot(normalize(in.world_normal), lights.directional_lights[0].direction_to_light), 0.0);
+    }
+
+    return vec4<f32>(color.rgb * in.color.rgb * (0.4 + 0.6 * diffuse), color.a);
+}
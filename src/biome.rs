@@ -0,0 +1,265 @@
+use bevy::{math::DVec3, prelude::*};
+use noise::NoiseFn;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    noise_graph::NoiseGraph,
+    block::{Block, BlockRegistry, RegistryError},
+    Noise,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Biome {
+    Plains,
+    Desert,
+    Mountains,
+    Tundra,
+}
+
+/// Number of filler blocks under the surface of each biome, set by the world generation config
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct FillerDepths {
+    pub plains: i32,
+    pub desert: i32,
+    pub mountains: i32,
+    pub tundra: i32,
+}
+
+impl Default for FillerDepths {
+    fn default() -> Self {
+        Self {
+            plains: 3,
+            desert: 5,
+            mountains: 1,
+            tundra: 3,
+        }
+    }
+}
+
+/// How a biome shapes the terrain
+#[derive(Clone, Debug)]
+pub struct BiomeInfo {
+    pub biome: Biome,
+    /// Climate the biome is the most likely to appear in, as (temperature, humidity) between -1 and 1
+    pub climate: (f64, f64),
+    /// Block at the top of each column
+    pub surface: Block,
+    /// Block between the surface and the stone
+    pub filler: Block,
+    /// Number of filler blocks under the surface
+    pub filler_depth: i32,
+    /// Lowest height of the terrain
+    pub base_height: f64,
+    /// Height of the terrain above the base height where the heightmap is the highest
+    pub amplitude: f64,
+    /// Exponent applied to the heightmap, flattening low terrain and sharpening peaks when above 1
+    pub exponent: f64,
+    /// Number of trees in each chunk column, up to `MAX_STRUCTURES`
+    pub trees: u32,
+    /// Number of boulders in each chunk column, up to `MAX_STRUCTURES`
+    pub boulders: u32,
+}
+
+impl BiomeInfo {
+    /// @returns The height of this biome's terrain, from a heightmap value between 0 and 1
+    pub fn height(&self, heightmap: f64) -> f64 {
+        self.base_height + self.amplitude * heightmap.powf(self.exponent)
+    }
+}
+
+/// Scale in blocks of the temperature and humidity noise
+const CLIMATE_SCALE: f64 = 512.0;
+/// Distance in climate space over which neighbouring biomes blend together
+const BLEND_DISTANCE: f64 = 0.25;
+
+const TEMPERATURE_OFFSET: DVec3 = DVec3::new(-7349.0, 0.0, 2203.0);
+const HUMIDITY_OFFSET: DVec3 = DVec3::new(3571.0, 0.0, -8923.0);
+
+/// Every biome of the world, selected from temperature and humidity noise
+#[derive(Resource, Clone)]
+pub struct Biomes {
+    pub biomes: Vec<BiomeInfo>,
+    /// Noise shared by every biome, giving the heightmap before it is shaped by each biome
+    pub heightmap: NoiseGraph,
+}
+
+impl Biomes {
+    pub fn from_registry(registry: &BlockRegistry, heightmap: NoiseGraph, depths: &FillerDepths) -> Result<Self, RegistryError> {
+        let grass = registry.by_name("grass")?;
+        let dirt = registry.by_name("dirt")?;
+        let stone = registry.by_name("stone")?;
+        let sand = registry.by_name("sand")?;
+        let snow = registry.by_name("snow")?;
+
+        Ok(Self {
+            biomes: vec![
+                BiomeInfo {
+                    biome: Biome::Plains,
+                    climate: (0.2, 0.3),
+                    surface: grass,
+                    filler: dirt,
+                    filler_depth: depths.plains,
+                    base_height: 40.0,
+                    amplitude: 40.0,
+                    exponent: 1.0,
+                    trees: 3,
+                    boulders: 0,
+                },
+                BiomeInfo {
+                    biome: Biome::Desert,
+                    climate: (0.7, -0.5),
+                    surface: sand,
+                    filler: sand,
+                    filler_depth: depths.desert,
+                    base_height: 44.0,
+                    amplitude: 16.0,
+                    exponent: 1.0,
+                    trees: 0,
+                    boulders: 0,
+                },
+                BiomeInfo {
+                    biome: Biome::Mountains,
+                    climate: (-0.1, -0.4),
+                    surface: stone,
+                    filler: stone,
+                    filler_depth: depths.mountains,
+                    base_height: 48.0,
+                    amplitude: 76.0,
+                    exponent: 1.6,
+                    trees: 0,
+                    boulders: 2,
+                },
+                BiomeInfo {
+                    biome: Biome::Tundra,
+                    climate: (-0.7, 0.2),
+                    surface: snow,
+                    filler: dirt,
+                    filler_depth: depths.tundra,
+                    base_height: 40.0,
+                    amplitude: 28.0,
+                    exponent: 1.2,
+                    trees: 1,
+                    boulders: 1,
+                },
+            ],
+            heightmap,
+        })
+    }
+
+    /// @returns The temperature and humidity at the given global column
+    pub fn climate(noise: &Noise, x: i32, z: i32) -> (f64, f64) {
+        let pos = DVec3::new(x as f64, 0.0, z as f64) / CLIMATE_SCALE;
+        (
+            noise.0.get((pos + TEMPERATURE_OFFSET).to_array()),
+            noise.0.get((pos + HUMIDITY_OFFSET).to_array()),
+        )
+    }
+
+    /// @returns The influence of each biome on the given global column, summing to 1
+    pub fn weights(&self, noise: &Noise, x: i32, z: i32) -> Vec<f64> {
+        let (temperature, humidity) = Self::climate(noise, x, z);
+
+        let mut weights = self
+            .biomes
+            .iter()
+            .map(|b| {
+                let distance = (b.climate.0 - temperature).powi(2) + (b.climate.1 - humidity).powi(2);
+                (-distance / (BLEND_DISTANCE * BLEND_DISTANCE)).exp()
+            })
+            .collect::<Vec<_>>();
+
+        let total: f64 = weights.iter().sum();
+        if total > 0.0 {
+            weights.iter_mut().for_each(|w| *w /= total);
+        }
+        weights
+    }
+
+    /// @returns The dominant biome of the given global column
+    pub fn biome_at(&self, noise: &Noise, x: i32, z: i32) -> Biome {
+        self.column(noise, x, z).0.biome
+    }
+
+    /// @returns The dominant biome of the given global column, and the height of the terrain blended between biomes
+    pub fn column(&self, noise: &Noise, x: i32, z: i32) -> (&BiomeInfo, i32) {
+        let weights = self.weights(noise, x, z);
+
+        let pos = DVec3::new(x as f64, 0.0, z as f64);
+        let heightmap = (self.heightmap.get(pos.to_array()) / 2.0 + 0.5).clamp(0.0, 1.0);
+
+        let height: f64 = self
+            .biomes
+            .iter()
+            .zip(&weights)
+            .map(|(b, w)| b.height(heightmap) * w)
+            .sum();
+        let height = height as i32;
+
+        let dominant = weights
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map_or(0, |(i, _)| i);
+
+        (&self.biomes[dominant], height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::utils::HashSet;
+
+    use super::*;
+    use crate::test_util::generator;
+
+    #[test]
+    fn biome_at_follows_the_closest_climate() {
+        let (noise, _, biomes, _) = generator(7);
+
+        let mut found = HashSet::new();
+        // Climate changes over hundreds of blocks, so columns are sampled far apart
+        for (x, z) in (-20..20).flat_map(|x| (-20..20).map(move |z| (x * 64, z * 64))) {
+            let (temperature, humidity) = Biomes::climate(&noise, x, z);
+            let closest = biomes
+                .biomes
+                .iter()
+                .min_by(|a, b| {
+                    let distance = |b: &BiomeInfo| (b.climate.0 - temperature).powi(2) + (b.climate.1 - humidity).powi(2);
+                    distance(a).total_cmp(&distance(b))
+                })
+                .unwrap();
+
+            let biome = biomes.biome_at(&noise, x, z);
+            assert_eq!(biome, closest.biome, "column ({x}, {z}) with climate ({temperature:.2}, {humidity:.2})");
+            found.insert(biome);
+        }
+        assert!(found.len() > 1, "only {found:?} was found");
+    }
+
+    #[test]
+    fn heights_blend_across_biome_borders() {
+        let (noise, _, biomes, _) = generator(7);
+
+        let (mut borders, mut largest_gap) = (0, 0.0f64);
+        // Short lines of adjacent columns, spread out so that some cross a border
+        for (x, z) in (-20..20).flat_map(|x| (-20..20).map(move |z| (x * 64, z * 64))) {
+            for x in x..x + 32 {
+                let (a, a_height) = biomes.column(&noise, x, z);
+                let (b, b_height) = biomes.column(&noise, x + 1, z);
+                if a.biome == b.biome {
+                    continue;
+                }
+                borders += 1;
+
+                assert!((a_height - b_height).abs() <= 4, "step of {} blocks between ({x}, {z}) and the next column", a_height - b_height);
+
+                // What the step would be if each column only took the height of its own biome
+                let heightmap = (biomes.heightmap.get([x as f64, 0.0, z as f64]) / 2.0 + 0.5).clamp(0.0, 1.0);
+                largest_gap = largest_gap.max((a.height(heightmap) - b.height(heightmap)).abs());
+            }
+        }
+        assert!(borders > 0, "no biome border was crossed");
+        assert!(largest_gap > 20.0, "biomes have close heights at every border, blending isn't tested");
+    }
+}
@@ -1,71 +1,356 @@
-use bevy::prelude::*;
+use std::{fmt, path::Path};
 
-#[derive(Default, Clone, Copy)]
-pub enum Block {
+use bevy::{prelude::*, utils::HashMap};
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::{fluid::{update_fluid, MAX_FLUID_LEVEL}, light::{brightness, MAX_LIGHT}, tick::TickContext};
+
+/// Number of ticks a block waits before falling, once the block below it changed
+const FALL_DELAY: u64 = 2;
+
+/// Light needed above a block for a spreading block to grow onto it
+const SPREAD_LIGHT: u8 = 9;
+
+/// Numeric id of a block type, as declared in the block registry
+#[derive(Default, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Block(pub u16);
+
+impl Block {
+    /// Air is always registered with id 0, so that a zeroed chunk is empty
+    pub const AIR: Block = Block(0);
+}
+
+/// Atlas coordinates of the textures of a block
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub enum BlockTextures {
+    All((i32, i32)),
+    Faces {
+        top: (i32, i32),
+        bottom: (i32, i32),
+        side: (i32, i32),
+    },
+}
+
+/// How the faces of a block are drawn, each kind being meshed separately
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BlockRender {
+    /// Not drawn at all
+    Invisible,
     #[default]
-    Air,
-    Grass,
-    Dirt,
-    Stone,
+    Opaque,
+    /// Drawn with the fully transparent pixels of its texture cut out, like leaves
+    Cutout,
+    /// Blended with what is behind it, drawn after every other block
+    Translucent,
 }
 
-impl Block {
+/// Part of a fluid a block stands for
+#[derive(Deserialize, Clone, Debug)]
+pub struct FluidLevel {
+    /// Name of the fluid in the fluid table
+    pub fluid: String,
+    /// Height of the fluid in the block, from 1 to `MAX_FLUID_LEVEL` for source blocks
+    pub level: u8,
+}
+
+/// What a block does when it is ticked, fluids being ticked according to the fluid table instead
+#[derive(Deserialize, Default, Clone, PartialEq, Eq, Debug)]
+pub enum BlockBehavior {
+    #[default]
+    None,
+    /// Grows onto nearby blocks of the given name that are lit and uncovered, and turns back into it once covered, like grass
+    Spreads(String),
+    /// Falls as an entity when the block below it isn't solid, like sand
+    Falls,
+}
+
+/// Properties of a block type, as described in the block definition file
+#[derive(Deserialize, Clone, Debug)]
+pub struct BlockInfo {
+    pub id: u16,
+    pub name: String,
+    #[serde(default)]
+    pub textures: Option<BlockTextures>,
+    /// Wether light and sight go through this block
+    #[serde(default)]
+    pub transparent: bool,
+    /// Wether this block is a full block, colliding with entities
+    #[serde(default = "default_true")]
+    pub solid: bool,
+    #[serde(default)]
+    pub render: BlockRender,
+    /// Wether faces between two blocks of this type are hidden, like between two glass blocks
+    #[serde(default = "default_true")]
+    pub cull_same: bool,
+    #[serde(default)]
+    pub fluid: Option<FluidLevel>,
+    #[serde(default)]
+    pub behavior: BlockBehavior,
+    /// Level of the light emitted by this block, up to 15
+    #[serde(default)]
+    pub emission: u8,
+    /// When structures overlap, the block with the highest priority is kept
+    #[serde(default = "default_structure_priority")]
+    pub structure_priority: u8,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_structure_priority() -> u8 {
+    1
+}
+
+impl BlockInfo {
     pub fn transparent(&self) -> bool {
-        use Block::*;
-        match self {
-            Air => true,
-            _ => false,
-        }
+        self.transparent
     }
 
     /// Returns wether the given block is a full block
     pub fn full(&self) -> bool {
-        use Block::*;
-        match self {
-            Air => false,
-            _ => true,
+        self.solid
+    }
+
+    /// @returns Wether this block hides the faces of the blocks touching it
+    pub fn occludes(&self) -> bool {
+        self.solid && self.render == BlockRender::Opaque
+    }
+
+    /// @returns Wether a falling block landing here takes the place of this block, instead of dropping as an item
+    pub fn replaceable(&self) -> bool {
+        self.render == BlockRender::Invisible || self.fluid.is_some()
+    }
+
+    /// @returns Wether both blocks are levels of the same fluid
+    pub fn same_fluid(&self, other: &BlockInfo) -> bool {
+        matches!((&self.fluid, &other.fluid), (Some(a), Some(b)) if a.fluid == b.fluid)
+    }
+
+    /// @returns Wether picking this block at random does anything
+    pub fn random_ticks(&self) -> bool {
+        matches!(self.behavior, BlockBehavior::Spreads(_))
+    }
+
+    /// @returns The number of ticks before this block updates after a change next to it, or None if it doesn't care
+    pub fn update_delay(&self, ctx: &TickContext) -> Option<u64> {
+        if let Some((fluid, _)) = ctx.fluids.get(Block(self.id)) {
+            return Some(fluid.tick_delay);
+        }
+
+        match self.behavior {
+            BlockBehavior::Falls => Some(FALL_DELAY),
+            _ => None,
         }
     }
 
-    pub fn uvs(&self, face: Face) -> Option<[Vec2; 4]> {
-        const TEXTURE_BLOCK_SIZE: f32 = 16.0;
-        const ATLAS_SIZE: f32 = 256.0;
+    /// Called when an update scheduled at the position of this block is due
+    pub fn scheduled_tick(&self, ctx: &mut TickContext, pos: IVec3) {
+        if self.fluid.is_some() {
+            update_fluid(ctx.manager, ctx.registry, ctx.fluids, pos);
+            return;
+        }
 
-        use Block::*;
-        let atlas_coordinate = match self {
-            Grass => match face {
-                Face::TOP => Some(IVec2::new(0, 1)),
-                face if face.is_side() => Some(IVec2::new(0, 0)),
-                _ => Some(IVec2::new(1, 0))
+        if self.behavior == BlockBehavior::Falls {
+            let below = pos - IVec3::Y;
+            // The block leaves the world as a falling entity, spawned by `falling::spawn_falling_blocks`
+            if ctx.manager.get_block(below).is_some_and(|b| !ctx.registry.get(b).full()) {
+                ctx.manager.set_block(pos, Block::AIR);
+                ctx.manager.falling_blocks.push((pos, Block(self.id)));
+            }
+        }
+    }
+
+    /// Called when this block is picked at random in a loaded chunk
+    pub fn random_tick(&self, ctx: &mut TickContext, pos: IVec3) {
+        let BlockBehavior::Spreads(onto) = &self.behavior else { return };
+        let Ok(onto) = ctx.registry.by_name(onto) else { return };
+
+        // Blocks of unloaded chunks are left alone rather than considered covered
+        let covered = |ctx: &TickContext, pos: IVec3| {
+            ctx.manager.get_block(pos + IVec3::Y).is_some_and(|b| {
+                let info = ctx.registry.get(b);
+                info.occludes() || info.fluid.is_some()
+            })
+        };
+
+        if covered(&*ctx, pos) {
+            ctx.manager.set_block(pos, onto);
+            return;
+        }
+
+        let target = pos + IVec3::new(ctx.rng.gen_range(-1..=1), ctx.rng.gen_range(-3..=1), ctx.rng.gen_range(-1..=1));
+        let lit = ctx.manager.get_light(target + IVec3::Y).is_some_and(|l| brightness(l) >= SPREAD_LIGHT);
+        if ctx.manager.get_block(target) == Some(onto) && lit && !covered(&*ctx, target) {
+            ctx.manager.set_block(target, Block(self.id));
+        }
+    }
+
+    pub fn atlas_coordinate(&self, face: Face) -> Option<IVec2> {
+        let (x, y) = match self.textures? {
+            BlockTextures::All(c) => c,
+            BlockTextures::Faces { top, bottom, side } => match face {
+                Face::TOP => top,
+                Face::BOTTOM => bottom,
+                _ => side,
             },
-            Dirt => Some(IVec2::new(1, 0)),
-            Stone => Some(IVec2::new(2, 0)),
-            _ => None,
         };
+        Some(IVec2::new(x, y))
+    }
 
-        atlas_coordinate
-            // Get the 2 corners in uv space
-            .and_then(|c| {
-                Some((
-                    c.as_vec2() * TEXTURE_BLOCK_SIZE / ATLAS_SIZE,
-                    ((c + 1).as_vec2() * TEXTURE_BLOCK_SIZE - 1.0) / ATLAS_SIZE,
-                ))
-            })
-            // Get the 4 corners
-            .and_then(|(uv0, uv1)| {
-                Some([uv0, Vec2::new(uv1.x, uv0.y), uv1, Vec2::new(uv0.x, uv1.y)])
-            })
-            // Rotate according to the face (clockwise order and all that jazz)
-            .and_then(|mut uvs| {
-                match face {
-                    Face::WEST | Face::SOUTH => { uvs.reverse(); Some(uvs) },
-                    _ => Some(uvs)
+    /// @returns The atlas tile of the given face, with missing textures pointing to the last tile of the atlas
+    pub fn tile(&self, face: Face) -> IVec2 {
+        self.atlas_coordinate(face).unwrap_or(IVec2::splat(15))
+    }
+}
+
+#[derive(Debug)]
+pub enum RegistryError {
+    Io(std::io::Error),
+    Parse(ron::error::SpannedError),
+    /// Two definitions share the same id
+    DuplicateId { id: u16, first: String, second: String },
+    /// Two definitions share the same name
+    DuplicateName { name: String, first: u16, second: u16 },
+    /// Ids must be contiguous and start at 0, this id has no definition
+    MissingId(u16),
+    /// Block 0 has to be air
+    AirNotFirst(String),
+    /// A block was requested by a name that isn't registered
+    UnknownName(String),
+    /// A block emits more light than the maximum light level
+    InvalidEmission { name: String, emission: u8 },
+    /// A fluid block has a level of 0 or above the maximum
+    InvalidFluidLevel { name: String, level: u8 },
+    /// A fluid block isn't translucent, the only meshes whose surface can be lowered
+    FluidNotTranslucent(String),
+    /// A block spreads onto a block that isn't registered
+    UnknownSpreadTarget { name: String, target: String },
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use RegistryError::*;
+        match self {
+            Io(e) => write!(f, "could not read block definitions: {e}"),
+            Parse(e) => write!(f, "could not parse block definitions: {e}"),
+            DuplicateId { id, first, second } => {
+                write!(f, "block id {id} is used by both `{first}` and `{second}`")
+            }
+            DuplicateName { name, first, second } => {
+                write!(f, "block name `{name}` is used by both id {first} and id {second}")
+            }
+            MissingId(id) => write!(f, "no block is defined with id {id} (ids must be contiguous from 0)"),
+            AirNotFirst(name) => write!(f, "block id 0 must be `air`, found `{name}`"),
+            UnknownName(name) => write!(f, "unknown block `{name}`"),
+            InvalidEmission { name, emission } => {
+                write!(f, "block `{name}` emits light {emission}, the maximum being {MAX_LIGHT}")
+            }
+            InvalidFluidLevel { name, level } => {
+                write!(f, "block `{name}` has fluid level {level}, levels going from 1 to {MAX_FLUID_LEVEL}")
+            }
+            FluidNotTranslucent(name) => write!(f, "fluid block `{name}` has to be rendered as `Translucent`"),
+            UnknownSpreadTarget { name, target } => write!(f, "block `{name}` spreads onto unknown block `{target}`"),
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {}
+
+/// Lookup table from block ids to their properties
+#[derive(Resource, Clone)]
+pub struct BlockRegistry {
+    blocks: Vec<BlockInfo>,
+    names: HashMap<String, Block>,
+}
+
+impl BlockRegistry {
+    /// Reads a list of block definitions from a RON file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, RegistryError> {
+        let source = std::fs::read_to_string(path).map_err(RegistryError::Io)?;
+        let definitions: Vec<BlockInfo> = ron::from_str(&source).map_err(RegistryError::Parse)?;
+
+        Self::from_definitions(definitions)
+    }
+
+    pub fn from_definitions(mut definitions: Vec<BlockInfo>) -> Result<Self, RegistryError> {
+        definitions.sort_by_key(|b| b.id);
+
+        let mut names = HashMap::new();
+        for (expected, (index, block)) in (0u16..).zip(definitions.iter().enumerate()) {
+            if index > 0 && definitions[index - 1].id == block.id {
+                return Err(RegistryError::DuplicateId {
+                    id: block.id,
+                    first: definitions[index - 1].name.clone(),
+                    second: block.name.clone(),
+                });
+            }
+            if block.id != expected {
+                return Err(RegistryError::MissingId(expected));
+            }
+
+            if block.emission > MAX_LIGHT {
+                return Err(RegistryError::InvalidEmission { name: block.name.clone(), emission: block.emission });
+            }
+
+            if let Some(fluid) = block.fluid.as_ref().filter(|f| !(1..=MAX_FLUID_LEVEL).contains(&f.level)) {
+                return Err(RegistryError::InvalidFluidLevel { name: block.name.clone(), level: fluid.level });
+            }
+            if block.fluid.is_some() && block.render != BlockRender::Translucent {
+                return Err(RegistryError::FluidNotTranslucent(block.name.clone()));
+            }
+
+            if let Some(first) = names.insert(block.name.clone(), Block(block.id)) {
+                return Err(RegistryError::DuplicateName {
+                    name: block.name.clone(),
+                    first: first.0,
+                    second: block.id,
+                });
+            }
+        }
+
+        match definitions.first() {
+            None => return Err(RegistryError::MissingId(0)),
+            Some(air) if air.name != "air" => return Err(RegistryError::AirNotFirst(air.name.clone())),
+            _ => {}
+        }
+
+        for block in &definitions {
+            if let BlockBehavior::Spreads(target) = &block.behavior {
+                if !names.contains_key(target) {
+                    return Err(RegistryError::UnknownSpreadTarget { name: block.name.clone(), target: target.clone() });
                 }
-            })
+            }
+        }
+
+        Ok(Self { blocks: definitions, names })
+    }
+
+    /// Panics if the block wasn't created from this registry
+    #[inline]
+    pub fn get(&self, block: Block) -> &BlockInfo {
+        &self.blocks[block.0 as usize]
+    }
+
+    pub fn try_get(&self, block: Block) -> Option<&BlockInfo> {
+        self.blocks.get(block.0 as usize)
+    }
+
+    pub fn by_name(&self, name: &str) -> Result<Block, RegistryError> {
+        self.names
+            .get(name)
+            .copied()
+            .ok_or_else(|| RegistryError::UnknownName(name.to_owned()))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Block, &BlockInfo)> {
+        self.blocks.iter().map(|b| (Block(b.id), b))
     }
 }
 
-#[derive(Clone, Copy)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum Face {
     TOP,
     BOTTOM,
@@ -76,6 +361,8 @@ pub enum Face {
 }
 
 impl Face {
+    pub const ALL: [Face; 6] = [Face::TOP, Face::BOTTOM, Face::EAST, Face::WEST, Face::NORTH, Face::SOUTH];
+
     pub const fn normal(self) -> IVec3 {
         use Face::*;
         match self {
@@ -88,6 +375,11 @@ impl Face {
         }
     }
 
+    /// @returns The face pointing in the given direction, if it is a unit axis
+    pub fn from_normal(normal: IVec3) -> Option<Face> {
+        Face::ALL.into_iter().find(|f| f.normal() == normal)
+    }
+
     pub const fn normal_vec3(self) -> Vec3 {
         use Face::*;
         match self {
@@ -100,17 +392,45 @@ impl Face {
         }
     }
 
-    pub const fn is_any(self) -> bool {
-        true
+    pub const fn is_side(self) -> bool {
+        use Face::*;
+        matches!(self, EAST | WEST | NORTH | SOUTH)
     }
+}
 
-    pub const fn is_vertical(self) -> bool {
-        use Face::*;
-        matches!(self, TOP | BOTTOM)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// @returns The definitions of air followed by the given blocks
+    fn definitions(blocks: &str) -> Vec<BlockInfo> {
+        ron::from_str(&format!("[(id: 0, name: \"air\", transparent: true, solid: false, render: Invisible), {blocks}]")).unwrap()
     }
 
-    pub const fn is_side(self) -> bool {
-        use Face::*;
-        matches!(self, EAST | WEST | NORTH | SOUTH)
+    #[test]
+    fn duplicate_names_are_rejected() {
+        let error = BlockRegistry::from_definitions(definitions("(id: 1, name: \"stone\"), (id: 2, name: \"stone\")"));
+        assert!(matches!(error, Err(RegistryError::DuplicateName { name, first: 1, second: 2 }) if name == "stone"));
+    }
+
+    #[test]
+    fn duplicate_ids_are_rejected() {
+        let error = BlockRegistry::from_definitions(definitions("(id: 1, name: \"stone\"), (id: 1, name: \"dirt\")"));
+        assert!(matches!(error, Err(RegistryError::DuplicateId { id: 1, .. })));
+    }
+
+    #[test]
+    fn missing_ids_are_rejected() {
+        let error = BlockRegistry::from_definitions(definitions("(id: 1, name: \"stone\"), (id: 3, name: \"dirt\")"));
+        assert!(matches!(error, Err(RegistryError::MissingId(2))));
+        assert!(matches!(BlockRegistry::from_definitions(Vec::new()), Err(RegistryError::MissingId(0))));
+    }
+
+    #[test]
+    fn blocks_file_is_valid() {
+        let registry = BlockRegistry::load("assets/blocks.ron").unwrap();
+        assert_eq!(registry.by_name("air").unwrap(), Block::AIR);
+        assert!(registry.try_get(Block(registry.iter().count() as u16)).is_none());
+        assert!(matches!(registry.by_name("unobtainium"), Err(RegistryError::UnknownName(_))));
     }
 }
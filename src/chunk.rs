@@ -1,14 +1,14 @@
-use std::sync::{Arc, Mutex};
+use std::{marker::PhantomData, sync::Arc};
 
 use bevy::{
-    math::{DVec3, Vec3A},
+    ecs::system::SystemParam,
     prelude::*,
-    render::{mesh::Indices, primitives::{Frustum, Aabb}, camera::CameraProjection}
+    render::{primitives::{Frustum, Aabb}, camera::CameraProjection, mesh::{Indices, VertexAttributeValues}},
+    tasks::{AsyncComputeTaskPool, Task},
 };
-use itertools::Itertools;
-use noise::NoiseFn;
+use futures_lite::future;
 
-use crate::{Noise, manager::{ChunkManager, CHUNK_SIZE, WORLD_HEIGHT, ChunkData}, block::{Block, Face}};
+use crate::{Noise, biome::Biomes, config::WorldGenConfig, material::ATTRIBUTE_VOXEL, manager::{player_key, ChunkData, ChunkManager, CHUNK_SIZE}, block::{Block, BlockRegistry, BlockRender, Face}, mesher::{build_mesh, sort_quads, ChunkMeshes, Mesher}, occlusion::{chunk_connectivity, visible_chunks, Connectivity}, ore::{place_ores, OreTable}, structure::{generate_structures, StructureBlocks}, terrain::{generate_chunk, CaveSettings, TerrainBlocks}};
 
 #[derive(Component)]
 pub struct Chunk {
@@ -24,216 +24,287 @@ pub struct NeedsTerrain;
 #[component(storage = "SparseSet")]
 pub struct NeedsMesh(pub u32);
 
-pub fn generate_terrain(
-    commands: Commands,
-    query: Query<(Entity, &Chunk, &Transform), With<NeedsTerrain>>,
-    manager: ResMut<ChunkManager>,
-    noise: Res<Noise>,
+/// Terrain of a chunk being generated in the background, along with the blocks of the structures it places
+#[derive(Component)]
+#[component(storage = "SparseSet")]
+pub struct TerrainTask(Task<(ChunkData, Vec<(IVec3, Block)>)>);
+
+/// Mesh of a chunk being built in the background, along with the connectivity of its faces
+#[derive(Component)]
+#[component(storage = "SparseSet")]
+pub struct MeshTask(Task<(ChunkMeshes, Connectivity)>);
+
+/// Meshes of the cutout and translucent blocks of a chunk, drawn by children of the chunk entity with their own material
+#[derive(Component)]
+pub struct LayerMeshes {
+    pub cutout: Handle<Mesh>,
+    pub translucent: Handle<Mesh>,
+    /// Camera position the translucent faces were last sorted from, None if they were never sorted
+    pub sorted_from: Option<Vec3>,
+}
+
+/// Distance the camera moves before translucent faces are sorted again
+const RESORT_DISTANCE: f32 = 1.0;
+
+/// Maximum number of finished background tasks applied each frame, so that a burst of results doesn't stall a frame
+#[derive(Resource)]
+pub struct TaskBudget {
+    pub terrain: usize,
+    pub meshes: usize,
+}
+
+impl Default for TaskBudget {
+    fn default() -> Self {
+        Self { terrain: 16, meshes: 16 }
+    }
+}
+
+/// Copy of everything terrain generation reads, shared by the tasks spawned in the same frame
+struct TerrainContext {
+    noise: Noise,
+    caves: CaveSettings,
+    biomes: Biomes,
+    blocks: TerrainBlocks,
+    ores: OreTable,
+    structure_blocks: StructureBlocks,
+}
+
+/// Resources terrain generation reads
+#[derive(SystemParam)]
+pub struct TerrainResources<'w, 's> {
+    noise: Res<'w, Noise>,
+    config: Res<'w, WorldGenConfig>,
+    biomes: Res<'w, Biomes>,
+    blocks: Res<'w, TerrainBlocks>,
+    ores: Res<'w, OreTable>,
+    structure_blocks: Res<'w, StructureBlocks>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+impl TerrainResources<'_, '_> {
+    fn context(&self) -> TerrainContext {
+        TerrainContext {
+            noise: self.noise.clone(),
+            caves: self.config.caves.clone(),
+            biomes: self.biomes.clone(),
+            blocks: *self.blocks,
+            ores: self.ores.clone(),
+            structure_blocks: *self.structure_blocks,
+        }
+    }
+}
+
+/// Starts generating the terrain of new chunks on the async compute pool
+pub fn generate_terrain(mut commands: Commands, query: Query<(Entity, &Chunk), With<NeedsTerrain>>, resources: TerrainResources) {
+    if query.is_empty() {
+        return;
+    }
+
+    let pool = AsyncComputeTaskPool::get();
+    let context = Arc::new(resources.context());
+
+    for (entity, chunk) in &query {
+        let key = chunk.key;
+        let context = context.clone();
+
+        let task = pool.spawn(async move {
+            let TerrainContext { noise, caves, biomes, blocks, ores, structure_blocks } = &*context;
+
+            let mut data = generate_chunk(key, noise, caves, biomes, blocks);
+            place_ores(key, noise, ores, &mut data);
+            let structures = generate_structures(key, noise, biomes, structure_blocks);
+            (data, structures)
+        });
+
+        commands.entity(entity).remove::<NeedsTerrain>().insert(TerrainTask(task));
+    }
+}
+
+/// Moves the terrain of finished generation tasks into the chunk manager, up to the frame's budget
+pub fn apply_terrain(
+    mut commands: Commands,
+    mut query: Query<(Entity, &Chunk, &mut TerrainTask)>,
+    mut manager: ResMut<ChunkManager>,
+    registry: Res<BlockRegistry>,
+    budget: Res<TaskBudget>,
 ) {
-    // let start = Instant::now();
-
-    // return;
-
-    let commands = Arc::new(Mutex::new(commands));
-    let manager = Arc::new(Mutex::new(manager));
-
-    query.par_for_each(10, |(entity, chunk, transform)| {
-        // If the chunk isn't yet loaded or it's already generated, skip it
-        if !manager.lock().unwrap().is_generated(chunk.key) {
-            let mut data = ChunkData::default();
-            for (x, z) in (0..CHUNK_SIZE).cartesian_product(0..CHUNK_SIZE) {
-                let mut pos = DVec3::new(x as f64, 0.0, z as f64) + transform.translation.as_dvec3();
-                pos.y = 0.0;
-                let height = noise.0.get((pos / 32.0).to_array()) / 2.0 + 0.5;
-                let height = height as f32 * CHUNK_SIZE as f32 * WORLD_HEIGHT as f32;
-                let height = height as usize;
-
-                for y in 0..CHUNK_SIZE {
-                    let y_real = y + chunk.key.y as usize * CHUNK_SIZE;
-                    data.data[z][y][x] = if y_real > height {
-                            Block::Air
-                        } else if y_real == height {
-                            Block::Grass
-                        } else if y_real > height-3 {
-                            Block::Dirt
-                        } else {
-                            Block::Stone
-                        };
-                }
-            }
-
-            data.generated = true;
-
-            *manager.lock().unwrap().chunks.get_mut(&chunk.key).unwrap() = data;
+    let mut applied = 0;
+    for (entity, chunk, mut task) in &mut query {
+        if applied >= budget.terrain {
+            break;
+        }
+        let Some((mut data, structures)) = future::block_on(future::poll_once(&mut task.0)) else { continue };
+
+        commands.entity(entity).remove::<TerrainTask>();
+        applied += 1;
+
+        // The chunk may have been unloaded while it was generating
+        if !manager.is_loaded(chunk.key) || manager.is_generated(chunk.key) {
+            continue;
         }
 
-        commands.lock().unwrap().entity(entity).remove::<NeedsTerrain>();
-        // Limit chunk generation to 5ms
-        // if Instant::now() - start > Duration::from_millis(5) { break }
-    });
+        manager.place_structures(&registry, chunk.key, &mut data, structures);
+        manager.chunks.insert(chunk.key, data);
+    }
+}
+
+/// @returns Wether the chunk is made of a single block that has no visible face:
+/// either an empty chunk, or a full opaque chunk surrounded by full opaque chunks
+fn has_no_faces(manager: &ChunkManager, registry: &BlockRegistry, key: IVec3, data: &ChunkData) -> bool {
+    let single_full = |data: &ChunkData| data.generated && data.single_block().is_some_and(|b| registry.get(b).occludes());
+
+    match data.single_block() {
+        Some(block) if registry.get(block).render == BlockRender::Invisible => true,
+        Some(_) if single_full(data) => Face::ALL
+            .iter()
+            .all(|face| manager.chunks.get(&(key + face.normal())).is_some_and(single_full)),
+        _ => false,
+    }
 }
 
+/// Starts building the meshes of chunks whose neighbourhood is ready, from a snapshot of it
 pub fn generate_mesh(
-    commands: Commands,
-    query: Query<(Entity, &Chunk, &Handle<Mesh>, &NeedsMesh)>,
+    mut commands: Commands,
+    query: Query<(Entity, &Chunk, &NeedsMesh)>,
     manager: Res<ChunkManager>,
-    meshes: ResMut<Assets<Mesh>>,
+    registry: Res<BlockRegistry>,
+    mesher: Res<Mesher>,
+    mut shared: Local<Option<Arc<BlockRegistry>>>,
 ) {
-    // let start = Instant::now();
-    let commands = Arc::new(Mutex::new(commands));
-    let meshes = Arc::new(Mutex::new(meshes));
-    query.par_for_each(10, |(entity, chunk, mesh, &NeedsMesh(lod))| {
-        let Some(data) = manager.chunks.get(&chunk.key) else { return; };
-
-        if !data.generated || ChunkManager::adjacent_keys(chunk.key).any(|c| !manager.is_generated(c)) {
-            return;
-        }
+    // Tasks share a copy of the registry, only made again when the registry changes
+    if registry.is_changed() || shared.is_none() {
+        *shared = Some(Arc::new(registry.clone()));
+    }
+    if query.is_empty() {
+        return;
+    }
+
+    let pool = AsyncComputeTaskPool::get();
+    let registry = shared.clone().unwrap();
 
-        let mut vertices = Vec::new();
-        let mut normals = Vec::new();
-        let mut texture_coordinates = Vec::new();
-        let mut indices = Vec::new();
-
-        let mut add_face = |block: Block, local_pos: IVec3, face: Face, points: [Vec3; 4]| {
-            // Return early if the adjacent face is not visible
-            let lod_num = 2u32.pow(lod);
-            if manager
-                .get_with_adjacent(chunk.key, local_pos + face.normal()*lod_num as i32)
-                .unwrap_or(Block::Air)
-                .full()
-            {
-                return;
-            }
-
-            let idx = vertices.len() as u32;
-
-            let lod_multiplier = Vec3::new(lod_num as f32, 1.0, lod_num as f32);
-            let pos = local_pos.as_vec3() + Vec3::splat(0.5) * lod_multiplier;
-            for p in points {
-                vertices.push((pos + p*lod_multiplier).to_array());
-                normals.push(face.normal_vec3().to_array());
-            }
-
-            let uvs = block.uvs(face).unwrap_or([Vec2::ONE; 4]);
-            texture_coordinates.extend_from_slice(&uvs);
-            indices.extend_from_slice(&[idx + 2, idx + 1, idx, idx, idx + 3, idx + 2]);
-        };
-
-        for (block, x, y, z) in data.all_blocks_lod(lod) {
-            if block.transparent() {
-                continue;
-            }
-
-            let local_pos = IVec3::new(x as i32, y as i32, z as i32);
-
-            add_face(
-                block,
-                local_pos,
-                Face::TOP,
-                [
-                    Vec3::new(0.5, 0.5, -0.5),
-                    Vec3::new(0.5, 0.5, 0.5),
-                    Vec3::new(-0.5, 0.5, 0.5),
-                    Vec3::new(-0.5, 0.5, -0.5),
-                ],
-            );
-
-            add_face(
-                block,
-                local_pos,
-                Face::BOTTOM,
-                [
-                    Vec3::new(0.5, -0.5, 0.5),
-                    Vec3::new(0.5, -0.5, -0.5),
-                    Vec3::new(-0.5, -0.5, -0.5),
-                    Vec3::new(-0.5, -0.5, 0.5),
-                ],
-            );
-
-            add_face(
-                block,
-                local_pos,
-                Face::EAST,
-                [
-                    Vec3::new(0.5, 0.5, 0.5),
-                    Vec3::new(0.5, 0.5, -0.5),
-                    Vec3::new(0.5, -0.5, -0.5),
-                    Vec3::new(0.5, -0.5, 0.5),
-                ],
-            );
-
-            add_face(
-                block,
-                local_pos,
-                Face::WEST,
-                [
-                    Vec3::new(-0.5, -0.5, 0.5),
-                    Vec3::new(-0.5, -0.5, -0.5),
-                    Vec3::new(-0.5, 0.5, -0.5),
-                    Vec3::new(-0.5, 0.5, 0.5),
-                ],
-            );
-
-            add_face(
-                block,
-                local_pos,
-                Face::NORTH,
-                [
-                    Vec3::new(-0.5, 0.5, 0.5),
-                    Vec3::new(0.5, 0.5, 0.5),
-                    Vec3::new(0.5, -0.5, 0.5),
-                    Vec3::new(-0.5, -0.5, 0.5),
-                ],
-            );
-
-            add_face(
-                block,
-                local_pos,
-                Face::SOUTH,
-                [
-                    Vec3::new(-0.5, -0.5, -0.5),
-                    Vec3::new(0.5, -0.5, -0.5),
-                    Vec3::new(0.5, 0.5, -0.5),
-                    Vec3::new(-0.5, 0.5, -0.5),
-                ],
-            );
+    for (entity, chunk, &NeedsMesh(lod)) in &query {
+        let Some(data) = manager.chunks.get(&chunk.key) else { continue };
+
+        // Sky and buried chunks are skipped without copying their neighbourhood
+        if data.generated && has_no_faces(&manager, &registry, chunk.key, data) {
+            let connectivity = chunk_connectivity(data, &registry);
+            let task = pool.spawn(async move { (ChunkMeshes::default(), connectivity) });
+            commands.entity(entity).remove::<NeedsMesh>().insert(MeshTask(task));
+            continue;
         }
 
-        let mut meshes = meshes.lock().unwrap();
-        let mesh = meshes.get_mut(mesh).unwrap();
-        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
-        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
-        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, texture_coordinates);
-        mesh.set_indices(Some(Indices::U32(indices)));
+        if !data.generated || !data.lit || ChunkManager::adjacent_keys(chunk.key).any(|c| !manager.is_generated(c)) {
+            continue;
+        }
 
-        // drop(mesh_lock);
+        let key = chunk.key;
+        let snapshot = manager.snapshot(key);
+        let registry = registry.clone();
+        let mesher = *mesher;
 
-        commands.lock().unwrap().entity(entity).remove::<NeedsMesh>();
+        let task = pool.spawn(async move {
+            // Connectivity comes from the real blocks, a downsampled chunk may close off small caves
+            let connectivity = chunk_connectivity(&snapshot.chunks[&key], &registry);
+            let snapshot = if lod > 0 { snapshot.downsample(lod, &registry) } else { snapshot };
+            (build_mesh(mesher, &snapshot, &registry, key, lod), connectivity)
+        });
 
-        // if Instant::now() - start > Duration::from_millis(5) { break }
-    });
+        // Replaces the task of an outdated mesh, dropping it cancels it
+        commands.entity(entity).remove::<NeedsMesh>().insert(MeshTask(task));
+    }
 }
 
-/// Do fast frustum culling on chunks based on their position
-pub fn cull_meshes(mut chunks: Query<(&Chunk, &mut Visibility)>, camera: Query<(&Transform, &Projection), With<Camera>>) {
-    let (transform, projection) = camera.single();
+/// Uploads the meshes of finished meshing tasks, up to the frame's budget
+pub fn apply_meshes(
+    mut commands: Commands,
+    mut query: Query<(Entity, &Chunk, &Handle<Mesh>, &mut LayerMeshes, &mut MeshTask)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut manager: ResMut<ChunkManager>,
+    budget: Res<TaskBudget>,
+) {
+    let mut applied = 0;
+    for (entity, chunk, mesh, mut layers, mut task) in &mut query {
+        if applied >= budget.meshes {
+            break;
+        }
+        let Some((chunk_meshes, connectivity)) = future::block_on(future::poll_once(&mut task.0)) else { continue };
+
+        chunk_meshes.opaque.apply(meshes.get_mut(mesh).unwrap());
+        chunk_meshes.cutout.apply(meshes.get_mut(&layers.cutout).unwrap());
+        chunk_meshes.translucent.apply(meshes.get_mut(&layers.translucent).unwrap());
+        layers.sorted_from = None;
+        manager.connectivity.insert(chunk.key, connectivity);
+        commands.entity(entity).remove::<MeshTask>();
+        applied += 1;
+    }
+}
 
+/// @returns The frustum seen by a camera
+pub fn camera_frustum(transform: &Transform, projection: &Projection) -> Frustum {
     let view_projection = projection.get_projection_matrix() * transform.compute_matrix().inverse();
-    let frustum = Frustum::from_view_projection(
+    Frustum::from_view_projection(
         &view_projection,
         &transform.translation,
         &transform.back(),
         projection.far()
-    );
+    )
+}
+
+/// @returns Wether any part of the chunk is inside of the frustum
+pub fn chunk_in_frustum(frustum: &Frustum, key: IVec3) -> bool {
+    let chunk_size = CHUNK_SIZE as f32;
+    let aabb = Aabb::from_min_max(Vec3::ZERO, Vec3::splat(chunk_size));
+    let position = key.as_vec3() * chunk_size;
 
-    // let mut a = false;
+    frustum.intersects_obb(&aabb, &Mat4::from_translation(position), false)
+}
+
+/// Hides the chunks outside of the frustum, and the ones hidden behind other chunks
+pub fn cull_meshes(
+    mut chunks: Query<(&Chunk, &mut Visibility)>,
+    camera: Query<(&Transform, &Projection), With<Camera>>,
+    manager: Res<ChunkManager>,
+) {
+    let (transform, projection) = camera.single();
+    let frustum = camera_frustum(transform, projection);
+    let camera_key = player_key(transform);
+
+    let visible_chunks = visible_chunks(&manager, camera_key, &frustum);
 
     for (chunk, mut visible) in &mut chunks {
-        let chunk_size = CHUNK_SIZE as f32;
-        let aabb = Aabb::from_min_max(Vec3::ZERO, Vec3::splat(chunk_size));
-        let position = chunk.key.as_vec3() * chunk_size;
+        // Avoid triggering change detection when nothing changed
+        let is_visible = visible_chunks.contains(&chunk.key);
+        if visible.is_visible != is_visible {
+            visible.is_visible = is_visible;
+        }
+    }
+}
+
+/// Sorts the translucent faces of every chunk from back to front, whenever the camera moved far enough
+pub fn sort_translucent(
+    mut chunks: Query<(&Chunk, &mut LayerMeshes)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    camera: Query<&Transform, With<Camera>>,
+) {
+    let eye = camera.single().translation;
 
-        visible.is_visible = frustum.intersects_obb(&aabb, &Mat4::from_translation(position), false)
+    for (chunk, mut layers) in &mut chunks {
+        if layers.sorted_from.is_some_and(|from| from.distance(eye) < RESORT_DISTANCE) {
+            continue;
+        }
+        layers.sorted_from = Some(eye);
+
+        // Most chunks have no translucent faces, and getting their mesh mutably would upload it again
+        let Some(Indices::U32(indices)) = meshes.get(&layers.translucent).and_then(Mesh::indices) else { continue };
+        if indices.is_empty() {
+            continue;
+        }
 
+        let mesh = meshes.get_mut(&layers.translucent).unwrap();
+        let Some(VertexAttributeValues::Uint32(vertices)) = mesh.attribute(ATTRIBUTE_VOXEL).cloned() else { continue };
+        if let Some(Indices::U32(indices)) = mesh.indices_mut() {
+            sort_quads(&vertices, indices, eye - chunk.key.as_vec3() * CHUNK_SIZE as f32);
+        }
     }
 }
@@ -0,0 +1,242 @@
+use std::{fmt, path::{Path, PathBuf}};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{biome::FillerDepths, noise_graph::NoiseNode, terrain::CaveSettings, tick::DEFAULT_RANDOM_TICKS};
+
+/// Seed of the world, either given directly or as text hashed into a number
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(untagged)]
+pub enum Seed {
+    Number(u32),
+    Text(String),
+}
+
+impl Seed {
+    /// Reads a seed typed by the user, text made only of digits being treated as a number
+    pub fn parse(text: &str) -> Self {
+        text.parse().map(Seed::Number).unwrap_or_else(|_| Seed::Text(text.to_owned()))
+    }
+
+    /// @returns The numeric value of the seed.
+    /// Text is hashed with FNV-1a, which unlike the standard library hasher is stable across compiler versions.
+    pub fn value(&self) -> u32 {
+        match self {
+            Seed::Number(n) => *n,
+            Seed::Text(text) => text
+                .bytes()
+                .fold(0x811C_9DC5, |hash: u32, b| (hash ^ b as u32).wrapping_mul(0x0100_0193)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Parse(ron::error::SpannedError),
+    Serialize(ron::Error),
+    /// The config was written by a newer version of the game
+    UnsupportedVersion(u32),
+    /// A command line argument is unknown or is missing its value
+    Argument(String),
+    /// The seed given on the command line isn't the one the world was generated with
+    SeedMismatch { world: PathBuf, given: u32, saved: u32 },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ConfigError::*;
+        match self {
+            Io(e) => write!(f, "could not read world generation config: {e}"),
+            Parse(e) => write!(f, "could not parse world generation config: {e}"),
+            Serialize(e) => write!(f, "could not write world generation config: {e}"),
+            UnsupportedVersion(v) => {
+                write!(f, "world generation config has version {v}, the latest supported version being {CONFIG_VERSION}")
+            }
+            Argument(arg) => write!(
+                f,
+                "invalid argument `{arg}`, usage: [--world <path>] [--config <path>] [--seed <seed>] [--random-ticks <count>] [--heightmap <png> [--size <pixels>]]"
+            ),
+            SeedMismatch { world, given, saved } => {
+                write!(f, "{} was generated with seed {saved}, it can't be loaded with seed {given}", world.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Version of the config format written by this version of the game
+pub const CONFIG_VERSION: u32 = 1;
+
+/// Everything the terrain of a world depends on.
+/// It is saved with the world, so that a saved world always regenerates the same terrain.
+#[derive(Resource, Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct WorldGenConfig {
+    /// Format of the config, so that configs written by a newer version of the game are rejected
+    pub version: u32,
+    pub seed: Seed,
+    /// Noise graph the height of the terrain is computed from, before being shaped by the biomes
+    pub heightmap: NoiseNode,
+    /// Caves, along with the thickness of the solid layers above and below them
+    pub caves: CaveSettings,
+    /// Thickness of the layer between the surface and the stone in each biome
+    pub filler_depths: FillerDepths,
+}
+
+impl Default for WorldGenConfig {
+    fn default() -> Self {
+        Self {
+            version: CONFIG_VERSION,
+            seed: Seed::Number(102),
+            heightmap: NoiseNode::default(),
+            caves: CaveSettings::default(),
+            filler_depths: FillerDepths::default(),
+        }
+    }
+}
+
+/// Name of the config file inside of the world save
+const CONFIG_FILE: &str = "worldgen.ron";
+
+impl WorldGenConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let source = std::fs::read_to_string(path).map_err(ConfigError::Io)?;
+        let config: Self = ron::from_str(&source).map_err(ConfigError::Parse)?;
+        if config.version > CONFIG_VERSION {
+            return Err(ConfigError::UnsupportedVersion(config.version));
+        }
+        Ok(config)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ConfigError> {
+        let source = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()).map_err(ConfigError::Serialize)?;
+        if let Some(parent) = path.as_ref().parent() {
+            std::fs::create_dir_all(parent).map_err(ConfigError::Io)?;
+        }
+        std::fs::write(path, source).map_err(ConfigError::Io)
+    }
+
+    /// @returns The config file given on the command line, or the default one, with the seed overriden
+    pub fn from_args(args: &Args) -> Result<Self, ConfigError> {
+        let mut config = if args.config.exists() { Self::load(&args.config)? } else { Self::default() };
+        if let Some(seed) = &args.seed {
+            config.seed = seed.clone();
+        }
+        Ok(config)
+    }
+
+    /// @returns The config of the world given on the command line.
+    /// A world that was already saved keeps its own config, and the config of a new world is written to its save.
+    /// A seed given for a world that was already saved has to be the seed it was generated with.
+    pub fn for_world(args: &Args) -> Result<Self, ConfigError> {
+        let saved = args.world.join(CONFIG_FILE);
+        if saved.exists() {
+            let config = Self::load(&saved)?;
+            if let Some(seed) = args.seed.as_ref().filter(|s| s.value() != config.seed.value()) {
+                return Err(ConfigError::SeedMismatch { world: args.world.clone(), given: seed.value(), saved: config.seed.value() });
+            }
+            return Ok(config);
+        }
+
+        let mut config = Self::from_args(args)?;
+        // Keep the numeric seed so that the hash function can't change the terrain of the world
+        config.seed = Seed::Number(config.seed.value());
+        config.version = CONFIG_VERSION;
+        config.save(&saved)?;
+
+        Ok(config)
+    }
+}
+
+/// Options given on the command line
+pub struct Args {
+    /// Directory of the world save
+    pub world: PathBuf,
+    /// Config used when creating a new world
+    pub config: PathBuf,
+    pub seed: Option<Seed>,
+    /// Renders the heightmap to this file instead of starting the game
+    pub heightmap: Option<PathBuf>,
+    /// Width and height in blocks of the rendered heightmap
+    pub size: u32,
+    /// Number of blocks picked at random in every loaded chunk each tick
+    pub random_ticks: u32,
+}
+
+impl Args {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, ConfigError> {
+        let mut parsed = Args {
+            world: PathBuf::from("saves/world"),
+            config: PathBuf::from("assets/worldgen.ron"),
+            seed: None,
+            heightmap: None,
+            size: 512,
+            random_ticks: DEFAULT_RANDOM_TICKS,
+        };
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| ConfigError::Argument(arg.clone()));
+            match arg.as_str() {
+                "--world" => parsed.world = value()?.into(),
+                "--config" => parsed.config = value()?.into(),
+                "--seed" => parsed.seed = Some(Seed::parse(&value()?)),
+                "--heightmap" => parsed.heightmap = Some(value()?.into()),
+                "--size" => parsed.size = value()?.parse().map_err(|_| ConfigError::Argument(arg.clone()))?,
+                "--random-ticks" => parsed.random_ticks = value()?.parse().map_err(|_| ConfigError::Argument(arg.clone()))?,
+                _ => return Err(ConfigError::Argument(arg)),
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::region::WorldSave;
+
+    /// @returns The arguments loading the given world, with the given seed
+    fn world_args(world: &Path, seed: Option<&str>) -> Args {
+        let mut args = vec!["--world".to_owned(), world.display().to_string()];
+        if let Some(seed) = seed {
+            args.extend(["--seed".to_owned(), seed.to_owned()]);
+        }
+        Args::parse(args).unwrap()
+    }
+
+    #[test]
+    fn text_seeds_are_hashed_with_fnv() {
+        // Reference values of 32 bit FNV-1a
+        assert_eq!(Seed::Text(String::new()).value(), 0x811C_9DC5);
+        assert_eq!(Seed::Text("a".into()).value(), 0xE40C_292C);
+        assert_eq!(Seed::Text("foobar".into()).value(), 0xBF9C_F968);
+        assert_eq!(Seed::parse("glacier").value(), Seed::parse("glacier").value());
+    }
+
+    #[test]
+    fn numeric_seeds_are_kept() {
+        assert_eq!(Seed::parse("4096"), Seed::Number(4096));
+        assert_eq!(Seed::parse("4096").value(), 4096);
+        assert_eq!(Seed::parse("-3"), Seed::Text("-3".into()));
+    }
+
+    #[test]
+    fn saved_worlds_keep_their_seed() {
+        let world = WorldSave::temporary("saved_worlds_keep_their_seed").path;
+
+        let created = WorldGenConfig::for_world(&world_args(&world, Some("glacier"))).unwrap();
+        assert_eq!(created.seed, Seed::Number(Seed::parse("glacier").value()));
+
+        // The same seed, or none at all, loads the saved config
+        for seed in [Some("glacier"), None] {
+            assert_eq!(WorldGenConfig::for_world(&world_args(&world, seed)).unwrap().seed, created.seed);
+        }
+        let error = WorldGenConfig::for_world(&world_args(&world, Some("7")));
+        assert!(matches!(error, Err(ConfigError::SeedMismatch { given: 7, saved, .. }) if saved == created.seed.value()));
+    }
+}
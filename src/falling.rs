@@ -0,0 +1,178 @@
+use bevy::{ecs::system::EntityCommands, pbr::NotShadowCaster, prelude::*, render::view::NoFrustumCulling};
+
+use crate::{
+    block::{Block, BlockRegistry, BlockRender},
+    light::{brightness, MAX_LIGHT},
+    manager::ChunkManager,
+    mesher::block_mesh,
+    physics::{VoxelBody, VoxelBodyBundle},
+    player::BoundingBox,
+    AtlasImage,
+};
+
+/// Size of the bounding box of falling blocks, a bit under a block so that they fit in holes one block wide
+const FALLING_SIZE: f32 = 0.98;
+/// Size of dropped items, drawn as small blocks
+const ITEM_SIZE: f32 = 0.25;
+/// Number of seconds before a dropped item disappears
+const ITEM_LIFETIME: f32 = 300.0;
+
+/// Block falling as an entity, put back in the world once it lands
+#[derive(Component)]
+pub struct FallingBlock(pub Block);
+
+/// Block dropped as an item, because it landed where it couldn't be placed
+#[derive(Component)]
+pub struct ItemDrop {
+    pub block: Block,
+    pub despawn: Timer,
+}
+
+/// Spawns a body with the given bounding box, drawn as a block.
+/// The mesh is held by a child, since block meshes start at their corner rather than their center.
+fn spawn_block_entity<'w, 's, 'a>(
+    commands: &'a mut Commands<'w, 's>,
+    meshes: &mut Assets<Mesh>,
+    registry: &BlockRegistry,
+    atlas: &AtlasImage,
+    block: Block,
+    light: u8,
+    bounding: &BoundingBox,
+) -> EntityCommands<'w, 's, 'a> {
+    let material = match registry.get(block).render {
+        BlockRender::Cutout => atlas.cutout.clone(),
+        BlockRender::Translucent => atlas.translucent.clone(),
+        _ => atlas.material.clone(),
+    };
+    let mesh = meshes.add(block_mesh(registry, block, light).into_mesh());
+
+    let size = bounding.half_extents * 2.0;
+
+    let mut entity = commands.spawn(VoxelBodyBundle::new(bounding.center, size));
+    entity.with_children(|parent| {
+        parent.spawn((
+            MaterialMeshBundle {
+                mesh,
+                material,
+                transform: Transform::from_translation(-size / 2.0).with_scale(size),
+                ..default()
+            },
+            NoFrustumCulling,
+            // Shadow passes expect a position attribute, which packed meshes don't have
+            NotShadowCaster,
+        ));
+    });
+    entity
+}
+
+/// Turns the blocks that started falling into entities
+pub fn spawn_falling_blocks(
+    mut commands: Commands,
+    mut manager: ResMut<ChunkManager>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    registry: Res<BlockRegistry>,
+    atlas: Res<AtlasImage>,
+) {
+    for (pos, block) in std::mem::take(&mut manager.falling_blocks) {
+        let light = manager.get_light(pos).map_or(MAX_LIGHT, brightness);
+        let bounding = BoundingBox { center: pos.as_vec3() + 0.5, half_extents: Vec3::splat(FALLING_SIZE / 2.0) };
+
+        spawn_block_entity(&mut commands, &mut meshes, &registry, &atlas, block, light, &bounding)
+            .insert((FallingBlock(block), Name::new(format!("Falling {}", registry.get(block).name))));
+    }
+}
+
+/// @returns Wether a falling block resting in the given cell can be put back in the world there.
+/// It has to rest on a full block, rather than hang over the edge of one, and its own cell has to be free.
+fn can_place(manager: &ChunkManager, registry: &BlockRegistry, cell: IVec3) -> bool {
+    let supported = manager.get_block(cell - IVec3::Y).is_some_and(|b| registry.get(b).full());
+    supported && manager.get_block(cell).is_some_and(|b| registry.get(b).replaceable())
+}
+
+/// Puts falling blocks back in the world once they land.
+/// A block landing where it can't be placed is dropped as an item instead.
+pub fn land_blocks(
+    mut commands: Commands,
+    query: Query<(Entity, &FallingBlock, &VoxelBody, &BoundingBox)>,
+    mut manager: ResMut<ChunkManager>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    registry: Res<BlockRegistry>,
+    atlas: Res<AtlasImage>,
+) {
+    for (entity, &FallingBlock(block), body, bounding) in &query {
+        if !body.on_ground {
+            continue;
+        }
+
+        commands.entity(entity).despawn_recursive();
+
+        let cell = bounding.center.floor().as_ivec3();
+        let placed = can_place(&manager, &registry, cell) && manager.set_block(cell, block).is_some();
+        if !placed {
+            let light = manager.get_light(cell).map_or(MAX_LIGHT, brightness);
+            let bounding = BoundingBox { center: bounding.center, half_extents: Vec3::splat(ITEM_SIZE / 2.0) };
+            let item = ItemDrop { block, despawn: Timer::from_seconds(ITEM_LIFETIME, TimerMode::Once) };
+            let name = Name::new(format!("Item {}", registry.get(item.block).name));
+            spawn_block_entity(&mut commands, &mut meshes, &registry, &atlas, block, light, &bounding).insert((item, name));
+        }
+    }
+}
+
+/// Removes the dropped items that have been lying around for too long
+pub fn despawn_items(mut commands: Commands, mut query: Query<(Entity, &mut ItemDrop)>, time: Res<Time>) {
+    for (entity, mut item) in &mut query {
+        if item.despawn.tick(time.delta()).finished() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::asset::AssetPlugin;
+
+    use super::*;
+    use crate::{manager::ChunkData, physics::step_bodies, storage::PaletteStorage};
+
+    #[test]
+    fn blocks_landing_off_full_blocks_drop_as_items() {
+        let registry = BlockRegistry::load("assets/blocks.ron").unwrap();
+        let [glass, sand] = ["glass", "sand"].map(|name| registry.by_name(name).unwrap());
+
+        // Floor of glass on the west half of the chunk, and nothing on the east half
+        let mut chunk = ChunkData::from_storage(PaletteStorage::Single(Block::AIR));
+        for (x, z) in ChunkData::slice().filter(|&(x, _)| x < 8) {
+            chunk.set_unchecked(IVec3::new(x as i32, 0, z as i32), glass);
+        }
+        chunk.generated = true;
+        let mut manager = ChunkManager::default();
+        manager.chunks.insert(IVec3::ZERO, chunk);
+
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(AssetPlugin::default())
+            .add_asset::<Mesh>()
+            .insert_resource(manager)
+            .insert_resource(registry)
+            .init_resource::<AtlasImage>()
+            .add_system(step_bodies)
+            .add_system(land_blocks.after(step_bodies));
+
+        // The second block hangs over the edge of the floor, its cell being above air
+        for x in [3.5, 8.25] {
+            let center = Vec3::new(x, 5.5, 3.5);
+            app.world.spawn((VoxelBodyBundle::new(center, Vec3::splat(FALLING_SIZE)), FallingBlock(sand)));
+        }
+        for _ in 0..120 {
+            app.update();
+        }
+
+        let manager = app.world.resource::<ChunkManager>();
+        assert_eq!(manager.get_block(IVec3::new(3, 1, 3)), Some(sand));
+        assert_eq!(manager.get_block(IVec3::new(8, 1, 3)), Some(Block::AIR));
+
+        assert_eq!(app.world.query::<&FallingBlock>().iter(&app.world).count(), 0);
+        let items = app.world.query::<(&ItemDrop, &BoundingBox)>().iter(&app.world).map(|(i, b)| (i.block, b.center.x)).collect::<Vec<_>>();
+        assert_eq!(items, [(sand, 8.25)]);
+    }
+}
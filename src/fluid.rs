@@ -0,0 +1,366 @@
+use std::{fmt, path::Path};
+
+use bevy::{prelude::*, utils::HashMap};
+use serde::Deserialize;
+
+use crate::{
+    block::{Block, BlockRegistry, Face},
+    manager::ChunkManager,
+    physics::VoxelBody,
+    player::{BoundingBox, Velocity},
+};
+
+/// Level of source blocks, flowing blocks having lower levels
+pub const MAX_FLUID_LEVEL: u8 = 8;
+
+/// Entry of the fluid file, the blocks of each level being declared in the block registry
+#[derive(Deserialize, Clone, Debug)]
+struct FluidDefinition {
+    name: String,
+    decay: u8,
+    tick_delay: u64,
+    renewable: bool,
+    drag: f32,
+    buoyancy: f32,
+}
+
+/// How a fluid flows, and how it slows down what is inside of it
+#[derive(Clone, Debug)]
+pub struct FluidInfo {
+    pub name: String,
+    /// Block of each level, the first one being level 1
+    levels: [Option<Block>; MAX_FLUID_LEVEL as usize],
+    /// Levels lost with every block the fluid flows sideways, under `MAX_FLUID_LEVEL` so that falling fluid has a level
+    pub decay: u8,
+    /// Number of world ticks between a change next to the fluid and its update
+    pub tick_delay: u64,
+    /// Wether a block between two source blocks becomes a source block itself
+    pub renewable: bool,
+    /// Factor applied to the velocity of entities inside of the fluid every physics step
+    pub drag: f32,
+    /// Upward velocity given to entities inside of the fluid every physics step
+    pub buoyancy: f32,
+}
+
+impl FluidInfo {
+    /// @returns The block of the given level, or None if the fluid doesn't have one
+    pub fn block(&self, level: u8) -> Option<Block> {
+        self.levels.get(level.checked_sub(1)? as usize).copied().flatten()
+    }
+
+    /// @returns The level of the fluid falling from the block above
+    pub fn falling_level(&self) -> u8 {
+        MAX_FLUID_LEVEL - self.decay
+    }
+}
+
+#[derive(Debug)]
+pub enum FluidError {
+    Io(std::io::Error),
+    Parse(ron::error::SpannedError),
+    /// A block is part of a fluid that isn't in the fluid file
+    UnknownFluid { block: String, fluid: String },
+    /// A fluid can flow to a level that no block stands for
+    MissingLevel { fluid: String, level: u8 },
+    /// A fluid loses every level in one block, so it couldn't flow or fall
+    InvalidDecay { fluid: String, decay: u8 },
+}
+
+impl fmt::Display for FluidError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use FluidError::*;
+        match self {
+            Io(e) => write!(f, "could not read fluids: {e}"),
+            Parse(e) => write!(f, "could not parse fluids: {e}"),
+            UnknownFluid { block, fluid } => write!(f, "block `{block}` is part of unknown fluid `{fluid}`"),
+            MissingLevel { fluid, level } => write!(f, "fluid `{fluid}` has no block for level {level}"),
+            InvalidDecay { fluid, decay } => {
+                write!(f, "fluid `{fluid}` has a decay of {decay}, it has to be between 1 and {}", MAX_FLUID_LEVEL - 1)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FluidError {}
+
+/// Every fluid, and the fluid each block is part of
+#[derive(Resource, Clone)]
+pub struct FluidTable {
+    pub fluids: Vec<FluidInfo>,
+    /// Index of the fluid and level of every fluid block
+    blocks: HashMap<Block, (usize, u8)>,
+}
+
+impl FluidTable {
+    /// Reads the fluids from a RON file, and gathers their levels from the block registry
+    pub fn load(path: impl AsRef<Path>, registry: &BlockRegistry) -> Result<Self, FluidError> {
+        let source = std::fs::read_to_string(path).map_err(FluidError::Io)?;
+        let definitions: Vec<FluidDefinition> = ron::from_str(&source).map_err(FluidError::Parse)?;
+
+        Self::from_definitions(definitions, registry)
+    }
+
+    fn from_definitions(definitions: Vec<FluidDefinition>, registry: &BlockRegistry) -> Result<Self, FluidError> {
+        // The falling level is the source level minus the decay, and has to be a real level
+        if let Some(d) = definitions.iter().find(|d| d.decay == 0 || d.decay >= MAX_FLUID_LEVEL) {
+            return Err(FluidError::InvalidDecay { fluid: d.name.clone(), decay: d.decay });
+        }
+
+        let mut fluids = definitions
+            .into_iter()
+            .map(|d| FluidInfo {
+                name: d.name,
+                levels: [None; MAX_FLUID_LEVEL as usize],
+                decay: d.decay,
+                tick_delay: d.tick_delay.max(1),
+                renewable: d.renewable,
+                drag: d.drag,
+                buoyancy: d.buoyancy,
+            })
+            .collect::<Vec<_>>();
+
+        let mut blocks = HashMap::new();
+        for (block, info) in registry.iter() {
+            let Some(level) = &info.fluid else { continue };
+            let Some(index) = fluids.iter().position(|f| f.name == level.fluid) else {
+                return Err(FluidError::UnknownFluid { block: info.name.clone(), fluid: level.fluid.clone() });
+            };
+
+            fluids[index].levels[level.level as usize - 1] = Some(block);
+            blocks.insert(block, (index, level.level));
+        }
+
+        // The source, the fluid falling from it, and every level they decay to need a block
+        for fluid in &fluids {
+            let decayed = (1..).map(|k| k * fluid.decay).take_while(|&d| d < MAX_FLUID_LEVEL).map(|d| MAX_FLUID_LEVEL - d);
+            if let Some(level) = [MAX_FLUID_LEVEL].into_iter().chain(decayed).find(|&l| fluid.block(l).is_none()) {
+                return Err(FluidError::MissingLevel { fluid: fluid.name.clone(), level });
+            }
+        }
+
+        Ok(Self { fluids, blocks })
+    }
+
+    /// @returns The fluid the block is part of, and its level
+    pub fn get(&self, block: Block) -> Option<(&FluidInfo, u8)> {
+        let &(index, level) = self.blocks.get(&block)?;
+        Some((&self.fluids[index], level))
+    }
+}
+
+/// @returns The level of the given fluid at the given position, or None if it isn't there
+fn level_at(manager: &ChunkManager, fluids: &FluidTable, fluid: &FluidInfo, pos: IVec3) -> Option<u8> {
+    let (other, level) = fluids.get(manager.get_block(pos)?)?;
+    (other.name == fluid.name).then_some(level)
+}
+
+/// @returns Wether a fluid of the given level can replace the block at the given position
+fn can_flow_into(manager: &ChunkManager, registry: &BlockRegistry, fluids: &FluidTable, fluid: &FluidInfo, pos: IVec3, level: u8) -> bool {
+    let Some(block) = manager.get_block(pos) else { return false };
+    let info = registry.get(block);
+
+    if info.fluid.is_none() {
+        !info.full()
+    } else {
+        level_at(manager, fluids, fluid, pos).is_some_and(|l| l < level)
+    }
+}
+
+/// Updates the fluid block at the given position, following the rules of classic voxel games:
+/// - a flowing block takes the level of its highest neighbour minus the decay, or the level of falling fluid
+///   when there is fluid above it, and dries up when that reaches 0
+/// - a block between two sources resting on something solid becomes a source, for renewable fluids
+/// - fluid flows down when it can, and spreads sideways with a lower level otherwise
+pub fn update_fluid(manager: &mut ChunkManager, registry: &BlockRegistry, fluids: &FluidTable, pos: IVec3) {
+    let Some((fluid, level)) = manager.get_block(pos).and_then(|b| fluids.get(b)) else { return };
+    let sides = Face::ALL.into_iter().filter(|f| f.is_side()).map(|f| pos + f.normal()).collect::<Vec<_>>();
+    let below = pos - IVec3::Y;
+
+    let new_level = if level == MAX_FLUID_LEVEL {
+        level
+    } else {
+        let levels = sides.iter().filter_map(|&side| level_at(manager, fluids, fluid, side)).collect::<Vec<_>>();
+        let sources = levels.iter().filter(|&&l| l == MAX_FLUID_LEVEL).count();
+        let supported = manager.get_block(below).is_some_and(|b| registry.get(b).full())
+            || level_at(manager, fluids, fluid, below) == Some(MAX_FLUID_LEVEL);
+
+        if fluid.renewable && sources >= 2 && supported {
+            MAX_FLUID_LEVEL
+        } else if level_at(manager, fluids, fluid, pos + IVec3::Y).is_some() {
+            fluid.falling_level()
+        } else {
+            levels.iter().max().map_or(0, |l| l.saturating_sub(fluid.decay))
+        }
+    };
+
+    if new_level != level {
+        // Changing the block schedules its neighbours, which will follow it
+        manager.set_block(pos, fluid.block(new_level).unwrap_or(Block::AIR));
+        if new_level == 0 {
+            return;
+        }
+    }
+
+    if can_flow_into(manager, registry, fluids, fluid, below, fluid.falling_level()) {
+        manager.set_block(below, fluid.block(fluid.falling_level()).unwrap());
+        return;
+    }
+    // Fluid falling into more of the same fluid doesn't spread on top of it
+    if level_at(manager, fluids, fluid, below).is_some_and(|l| l < MAX_FLUID_LEVEL) {
+        return;
+    }
+
+    let spread = new_level.saturating_sub(fluid.decay);
+    let Some(block) = fluid.block(spread) else { return };
+    for side in sides {
+        if can_flow_into(manager, registry, fluids, fluid, side, spread) {
+            manager.set_block(side, block);
+        }
+    }
+}
+
+/// Slows down the bodies inside of a fluid and pushes them up, before each physics step
+pub fn buoyancy(mut query: Query<(&BoundingBox, &mut Velocity), With<VoxelBody>>, manager: Res<ChunkManager>, fluids: Res<FluidTable>) {
+    for (bounding, mut velocity) in &mut query {
+        let Some((fluid, _)) = manager.get_block(bounding.center.floor().as_ivec3()).and_then(|b| fluids.get(b)) else { continue };
+
+        velocity.0 *= fluid.drag;
+        velocity.0.y += fluid.buoyancy;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+
+    use super::*;
+    use crate::{manager::ChunkData, storage::PaletteStorage};
+
+    /// @returns The definitions of the given fluids, with the properties that don't change how they flow
+    fn definitions(fluids: &[(&str, u8, bool)]) -> Vec<FluidDefinition> {
+        fluids
+            .iter()
+            .map(|&(name, decay, renewable)| FluidDefinition { name: name.into(), decay, tick_delay: 1, renewable, drag: 1.0, buoyancy: 0.0 })
+            .collect()
+    }
+
+    /// @returns A chunk of air with a floor of stone, along with the blocks and fluids
+    fn floor() -> (ChunkManager, BlockRegistry, FluidTable) {
+        let registry = BlockRegistry::load("assets/blocks.ron").unwrap();
+        let fluids = FluidTable::load("assets/fluids.ron", &registry).unwrap();
+        let stone = registry.by_name("stone").unwrap();
+
+        let mut chunk = ChunkData::from_storage(PaletteStorage::Single(Block::AIR));
+        for (x, z) in ChunkData::slice() {
+            chunk.set_unchecked(IVec3::new(x as i32, 0, z as i32), stone);
+        }
+        chunk.generated = true;
+        let mut manager = ChunkManager::default();
+        manager.chunks.insert(IVec3::ZERO, chunk);
+        (manager, registry, fluids)
+    }
+
+    /// Updates the fluids next to the changed blocks until nothing changes anymore, ignoring the tick delays
+    fn settle(manager: &mut ChunkManager, registry: &BlockRegistry, fluids: &FluidTable) {
+        for _ in 0..10_000 {
+            let changed = std::mem::take(&mut manager.block_updates);
+            if changed.is_empty() {
+                return;
+            }
+            let positions = changed.into_iter().flat_map(|pos| Face::ALL.into_iter().map(move |f| pos + f.normal()).chain([pos])).unique();
+            for pos in positions.collect_vec() {
+                update_fluid(manager, registry, fluids, pos);
+            }
+        }
+        panic!("fluids never stop flowing");
+    }
+
+    /// @returns The name of the fluid at the given position and its level, or None if there is no fluid
+    fn fluid_at(manager: &ChunkManager, fluids: &FluidTable, pos: IVec3) -> Option<(String, u8)> {
+        fluids.get(manager.get_block(pos)?).map(|(f, level)| (f.name.clone(), level))
+    }
+
+    fn source(fluids: &FluidTable, name: &str) -> Block {
+        fluids.fluids.iter().find(|f| f.name == name).and_then(|f| f.block(MAX_FLUID_LEVEL)).unwrap()
+    }
+
+    #[test]
+    fn flow_decays_with_distance() {
+        let (mut manager, registry, fluids) = floor();
+        manager.set_block(IVec3::new(0, 1, 8), source(&fluids, "water"));
+        settle(&mut manager, &registry, &fluids);
+
+        for distance in 1..MAX_FLUID_LEVEL {
+            let pos = IVec3::new(distance as i32, 1, 8);
+            assert_eq!(fluid_at(&manager, &fluids, pos), Some(("water".into(), MAX_FLUID_LEVEL - distance)), "wrong level at {pos}");
+        }
+        assert_eq!(manager.get_block(IVec3::new(MAX_FLUID_LEVEL as i32, 1, 8)), Some(Block::AIR));
+        assert_eq!(manager.get_block(IVec3::new(0, 2, 8)), Some(Block::AIR));
+    }
+
+    #[test]
+    fn falling_fluid_doesnt_spread() {
+        let (mut manager, registry, fluids) = floor();
+        manager.set_block(IVec3::new(8, 10, 8), source(&fluids, "water"));
+        settle(&mut manager, &registry, &fluids);
+
+        let falling = fluids.fluids[0].falling_level();
+        for y in 1..10 {
+            assert_eq!(fluid_at(&manager, &fluids, IVec3::new(8, y, 8)), Some(("water".into(), falling)), "column broken at {y}");
+            if y > 1 {
+                assert_eq!(manager.get_block(IVec3::new(9, y, 8)), Some(Block::AIR), "the fluid spread sideways at {y}");
+            }
+        }
+        // Spreading once it lands
+        assert_eq!(fluid_at(&manager, &fluids, IVec3::new(9, 1, 8)), Some(("water".into(), falling - 1)));
+    }
+
+    #[test]
+    fn only_renewable_fluids_form_sources() {
+        for (name, renewed) in [("water", true), ("lava", false)] {
+            let (mut manager, registry, fluids) = floor();
+            let source = source(&fluids, name);
+            manager.set_block(IVec3::new(6, 1, 8), source);
+            manager.set_block(IVec3::new(8, 1, 8), source);
+            settle(&mut manager, &registry, &fluids);
+
+            let (_, level) = fluid_at(&manager, &fluids, IVec3::new(7, 1, 8)).unwrap();
+            assert_eq!(level == MAX_FLUID_LEVEL, renewed, "{name} between two sources has level {level}");
+        }
+    }
+
+    #[test]
+    fn flow_dries_up_without_its_source() {
+        let (mut manager, registry, fluids) = floor();
+        let pos = IVec3::new(8, 1, 8);
+        manager.set_block(pos, source(&fluids, "water"));
+        settle(&mut manager, &registry, &fluids);
+        assert!(fluid_at(&manager, &fluids, pos + IVec3::X).is_some());
+
+        manager.set_block(pos, Block::AIR);
+        settle(&mut manager, &registry, &fluids);
+        let chunk = &manager.chunks[&IVec3::ZERO];
+        assert!(chunk.all_blocks().all(|(b, ..)| fluids.get(b).is_none()), "flowing fluid was left behind");
+    }
+
+    #[test]
+    fn invalid_decays_are_rejected() {
+        let registry = BlockRegistry::load("assets/blocks.ron").unwrap();
+        for decay in [0, MAX_FLUID_LEVEL] {
+            let error = FluidTable::from_definitions(definitions(&[("water", decay, true), ("lava", 2, false)]), &registry);
+            assert!(matches!(error, Err(FluidError::InvalidDecay { fluid, decay: d }) if fluid == "water" && d == decay));
+        }
+    }
+
+    #[test]
+    fn missing_levels_are_rejected() {
+        let registry = BlockRegistry::load("assets/blocks.ron").unwrap();
+
+        // Lava only has even levels
+        let error = FluidTable::from_definitions(definitions(&[("water", 1, true), ("lava", 1, false)]), &registry);
+        assert!(matches!(error, Err(FluidError::MissingLevel { fluid, level: 7 }) if fluid == "lava"));
+
+        let error = FluidTable::from_definitions(definitions(&[("water", 1, true)]), &registry);
+        assert!(matches!(error, Err(FluidError::UnknownFluid { fluid, .. }) if fluid == "lava"));
+    }
+}
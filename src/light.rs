@@ -0,0 +1,535 @@
+use std::collections::VecDeque;
+
+use bevy::{prelude::*, utils::HashSet};
+use itertools::Itertools;
+
+use crate::{
+    block::{Block, BlockRegistry, Face},
+    manager::{ChunkData, ChunkManager, CHUNK_SIZE, CHUNK_VOLUME},
+};
+
+pub const MAX_LIGHT: u8 = 15;
+
+/// Light level of every block of a chunk.
+/// Each value holds the sun light in its high nibble and the block light in its low nibble.
+#[derive(Clone, Debug)]
+pub enum LightData {
+    Uniform(u8),
+    Full(Vec<u8>),
+}
+
+impl Default for LightData {
+    fn default() -> Self {
+        LightData::Uniform(0)
+    }
+}
+
+impl LightData {
+    pub fn get(&self, index: usize) -> u8 {
+        match self {
+            LightData::Uniform(value) => *value,
+            LightData::Full(values) => values[index],
+        }
+    }
+
+    pub fn set(&mut self, index: usize, value: u8) {
+        if let LightData::Uniform(current) = *self {
+            if current == value {
+                return;
+            }
+            *self = LightData::Full(vec![current; CHUNK_VOLUME]);
+        }
+
+        let LightData::Full(values) = self else { unreachable!() };
+        values[index] = value;
+    }
+
+    /// @returns The number of bytes used by this light data, including its heap allocation
+    pub fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + match self {
+                LightData::Uniform(_) => 0,
+                LightData::Full(values) => values.capacity(),
+            }
+    }
+}
+
+/// Number of words of the column mask of a chunk
+pub const SKY_WORDS: usize = CHUNK_SIZE * CHUNK_SIZE / 64;
+
+/// Columns of a chunk that are above the terrain, with one bit per column.
+/// They are lit by the sun from above until the chunk over them is generated, their light then coming from it.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct SkyColumns(pub [u64; SKY_WORDS]);
+
+impl SkyColumns {
+    pub const NONE: SkyColumns = SkyColumns([0; SKY_WORDS]);
+    pub const ALL: SkyColumns = SkyColumns([u64::MAX; SKY_WORDS]);
+
+    pub fn get(&self, x: usize, z: usize) -> bool {
+        let i = x + z * CHUNK_SIZE;
+        (self.0[i / 64] >> (i % 64)) & 1 != 0
+    }
+
+    pub fn set(&mut self, x: usize, z: usize) {
+        let i = x + z * CHUNK_SIZE;
+        self.0[i / 64] |= 1 << (i % 64);
+    }
+}
+
+/// One of the two independent light channels
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Channel {
+    Sun,
+    Block,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 2] = [Channel::Sun, Channel::Block];
+
+    #[inline]
+    pub fn get(self, light: u8) -> u8 {
+        match self {
+            Channel::Sun => light >> 4,
+            Channel::Block => light & 0xF,
+        }
+    }
+
+    #[inline]
+    pub fn with(self, light: u8, value: u8) -> u8 {
+        match self {
+            Channel::Sun => (light & 0xF) | (value << 4),
+            Channel::Block => (light & 0xF0) | value,
+        }
+    }
+
+    /// @returns The light a block receives from a neighbour with the given level, in the given direction
+    /// Sun light shines straight down without fading
+    #[inline]
+    fn spread(self, level: u8, face: Face) -> u8 {
+        if self == Channel::Sun && level == MAX_LIGHT && face == Face::BOTTOM {
+            MAX_LIGHT
+        } else {
+            level.saturating_sub(1)
+        }
+    }
+}
+
+/// @returns The combined brightness of a light value
+pub fn brightness(light: u8) -> u8 {
+    Channel::Sun.get(light).max(Channel::Block.get(light))
+}
+
+/// @returns Wether light can go through the block at the given position, or None if its chunk isn't generated
+fn lets_light_through(manager: &ChunkManager, registry: &BlockRegistry, pos: IVec3) -> Option<bool> {
+    manager.get_block(pos).map(|b| registry.get(b).transparent())
+}
+
+/// Records that the light at `pos` changed, along with every chunk whose mesh samples it
+fn mark_changed(changed: &mut HashSet<IVec3>, pos: IVec3) {
+    let (key, local) = ChunkManager::get_keys(pos);
+    changed.insert(key);
+
+    let border = local.cmpeq(IVec3::ZERO).any() || local.cmpeq(IVec3::splat(CHUNK_SIZE as i32 - 1)).any();
+    if border {
+        for ((x, y), z) in (-1..=1).cartesian_product(-1..=1).cartesian_product(-1..=1) {
+            changed.insert(ChunkManager::get_keys(pos + IVec3::new(x, y, z)).0);
+        }
+    }
+}
+
+/// Spreads light from every position in the queue with a breadth first flood fill
+fn propagate(
+    manager: &mut ChunkManager,
+    registry: &BlockRegistry,
+    channel: Channel,
+    mut queue: VecDeque<IVec3>,
+    changed: &mut HashSet<IVec3>,
+) {
+    while let Some(pos) = queue.pop_front() {
+        let Some(light) = manager.get_light(pos) else { continue };
+        let level = channel.get(light);
+        if level == 0 {
+            continue;
+        }
+
+        for face in Face::ALL {
+            let next = pos + face.normal();
+            if lets_light_through(manager, registry, next) != Some(true) {
+                continue;
+            }
+
+            let target = channel.spread(level, face);
+            let current = manager.get_light(next).unwrap();
+            if channel.get(current) < target {
+                manager.set_light(next, channel.with(current, target));
+                mark_changed(changed, next);
+                queue.push_back(next);
+            }
+        }
+    }
+}
+
+/// Computes the light of a freshly generated chunk, spreading light in and out of its generated neighbours
+/// @returns The keys of the chunks whose light changed
+pub fn light_chunk(manager: &mut ChunkManager, registry: &BlockRegistry, key: IVec3) -> HashSet<IVec3> {
+    let mut changed = HashSet::new();
+    let origin = key * CHUNK_SIZE as i32;
+    let covered = manager.is_generated(key + IVec3::Y);
+
+    // The sun light of the chunk below is removed where this chunk may cover it, to flow back from this chunk instead
+    let newly_covered = newly_covered_columns(manager, registry, key, covered);
+    let mut sun = remove_light(manager, Channel::Sun, newly_covered, &mut changed);
+
+    let Some(chunk) = manager.chunks.get_mut(&key) else { return changed };
+    chunk.light = LightData::default();
+    changed.insert(key);
+
+    // Light can't enter chunks made of a single opaque block
+    if chunk.single_block().is_some_and(|b| !registry.get(b).transparent()) {
+        chunk.lit = true;
+        propagate(manager, registry, Channel::Sun, sun, &mut changed);
+        return changed;
+    }
+
+    let mut block = VecDeque::new();
+
+    // Light emitted by the blocks of the chunk
+    let emitters = chunk
+        .all_blocks()
+        .filter(|&(b, ..)| registry.get(b).emission > 0)
+        .map(|(b, x, y, z)| (registry.get(b).emission, IVec3::new(x as i32, y as i32, z as i32)))
+        .collect_vec();
+    for (emission, local) in emitters {
+        let light = chunk.get_light(local);
+        chunk.set_light(local, Channel::Block.with(light, emission));
+        block.push_back(origin + local);
+    }
+
+    if chunk.sky == SkyColumns::ALL && chunk.single_block() == Some(Block::AIR) {
+        // Empty sky is fully lit, only its borders can spread light to the neighbouring chunks
+        chunk.light = LightData::Uniform(Channel::Sun.with(0, MAX_LIGHT));
+        let last = CHUNK_SIZE - 1;
+        for (x, y, z) in ChunkData::all().filter(|&(x, y, z)| [x, y, z].iter().any(|&c| c == 0 || c == last)) {
+            sun.push_back(origin + IVec3::new(x as i32, y as i32, z as i32));
+        }
+    } else if !covered {
+        // Columns above the terrain are lit by the sun from above, once the chunk over them is generated its light flows in instead
+        let sky = chunk.sky;
+        for (x, z) in ChunkData::slice().filter(|&(x, z)| sky.get(x, z)) {
+            let local = IVec3::new(x as i32, CHUNK_SIZE as i32 - 1, z as i32);
+            if registry.get(chunk.get_unchecked(local)).transparent() {
+                let light = chunk.get_light(local);
+                chunk.set_light(local, Channel::Sun.with(light, MAX_LIGHT));
+                sun.push_back(origin + local);
+            }
+        }
+    }
+
+    // Let the light of the neighbouring chunks flow in
+    for face in Face::ALL {
+        for (i, j) in ChunkData::slice() {
+            let (i, j) = (i as i32, j as i32);
+            let local = match face {
+                Face::TOP => IVec3::new(i, CHUNK_SIZE as i32, j),
+                Face::BOTTOM => IVec3::new(i, -1, j),
+                Face::EAST => IVec3::new(CHUNK_SIZE as i32, i, j),
+                Face::WEST => IVec3::new(-1, i, j),
+                Face::NORTH => IVec3::new(i, j, CHUNK_SIZE as i32),
+                Face::SOUTH => IVec3::new(i, j, -1),
+            };
+
+            let pos = origin + local;
+            if manager.get_light(pos).is_some_and(|l| l != 0) {
+                sun.push_back(pos);
+                block.push_back(pos);
+            }
+        }
+    }
+
+    propagate(manager, registry, Channel::Sun, sun, &mut changed);
+    propagate(manager, registry, Channel::Block, block, &mut changed);
+
+    if let Some(chunk) = manager.chunks.get_mut(&key) {
+        chunk.lit = true;
+    }
+    changed
+}
+
+/// Removes the light of the given channel at the given positions, along with all the light that came from them
+/// @returns The lit positions around the darkened area, from which light has to be spread back in
+fn remove_light(
+    manager: &mut ChunkManager,
+    channel: Channel,
+    positions: impl IntoIterator<Item = IVec3>,
+    changed: &mut HashSet<IVec3>,
+) -> VecDeque<IVec3> {
+    let mut darken = VecDeque::new();
+    let mut relight = VecDeque::new();
+
+    for pos in positions {
+        let Some(light) = manager.get_light(pos) else { continue };
+        darken.push_back((pos, channel.get(light)));
+        manager.set_light(pos, channel.with(light, 0));
+        mark_changed(changed, pos);
+    }
+
+    while let Some((p, level)) = darken.pop_front() {
+        for face in Face::ALL {
+            let next = p + face.normal();
+            let Some(light) = manager.get_light(next) else { continue };
+            let next_level = channel.get(light);
+            if next_level == 0 {
+                continue;
+            }
+
+            // Neighbours lit by this block lose their light, brighter ones will fill the hole back
+            if next_level < level || (level > 0 && channel.spread(level, face) == next_level) {
+                manager.set_light(next, channel.with(light, 0));
+                mark_changed(changed, next);
+                darken.push_back((next, next_level));
+            } else {
+                relight.push_back(next);
+            }
+        }
+    }
+
+    relight
+}
+
+/// @returns The top blocks of the columns of the chunk under `key` that were lit as if nothing was above them,
+/// and that the blocks of `key` may now hide from the sun
+fn newly_covered_columns(manager: &ChunkManager, registry: &BlockRegistry, key: IVec3, covered: bool) -> Vec<IVec3> {
+    let (Some(chunk), Some(below)) = (manager.chunks.get(&key), manager.chunks.get(&(key - IVec3::Y))) else { return Vec::new() };
+    if !below.lit {
+        return Vec::new();
+    }
+
+    let empty_sky = chunk.sky == SkyColumns::ALL && chunk.single_block() == Some(Block::AIR);
+    let origin = key * CHUNK_SIZE as i32;
+    ChunkData::slice()
+        .filter(|&(x, z)| below.sky.get(x, z))
+        .filter(|&(x, z)| {
+            // Columns still under the open sky keep the light they had
+            let sunlit = chunk.sky.get(x, z) && (!covered || empty_sky);
+            let clear = (0..CHUNK_SIZE as i32).all(|y| registry.get(chunk.get_unchecked(IVec3::new(x as i32, y, z as i32))).transparent());
+            !(sunlit && clear)
+        })
+        .map(|(x, z)| origin + IVec3::new(x as i32, -1, z as i32))
+        .collect()
+}
+
+/// Updates the light around a block that was just replaced
+/// @returns The keys of the chunks whose light changed
+pub fn update_light(manager: &mut ChunkManager, registry: &BlockRegistry, pos: IVec3) -> HashSet<IVec3> {
+    let mut changed = HashSet::new();
+    let Some(block) = manager.get_block(pos) else { return changed };
+    let info = registry.get(block);
+
+    for channel in Channel::ALL {
+        // First remove all the light that could have come through or from this block
+        let mut relight = remove_light(manager, channel, [pos], &mut changed);
+
+        if channel == Channel::Block && info.emission > 0 {
+            let light = manager.get_light(pos).unwrap();
+            manager.set_light(pos, Channel::Block.with(light, info.emission));
+            relight.push_back(pos);
+        }
+        if info.transparent() {
+            for face in Face::ALL {
+                relight.push_back(pos + face.normal());
+            }
+        }
+
+        propagate(manager, registry, channel, relight, &mut changed);
+    }
+
+    changed
+}
+
+/// Computes the light of newly generated chunks and around modified blocks, and remeshes the chunks it reached
+pub fn light_chunks(
+    mut manager: ResMut<ChunkManager>,
+    registry: Res<BlockRegistry>,
+) {
+    let unlit = manager
+        .chunks
+        .iter()
+        .filter(|(_, c)| c.generated && !c.lit)
+        .map(|(&k, _)| k)
+        .collect_vec();
+
+    let mut changed = HashSet::new();
+    for key in unlit {
+        changed.extend(light_chunk(&mut manager, &registry, key));
+    }
+
+    let updates = std::mem::take(&mut manager.light_updates);
+    for pos in updates {
+        changed.extend(update_light(&mut manager, &registry, pos));
+    }
+
+    manager.remesh.extend(changed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{storage::PaletteStorage, terrain::generate_chunk, test_util::generator};
+
+    /// @returns A generated chunk of air, with a floor of the given block if any
+    fn chunk(sky: SkyColumns, floor: Option<Block>) -> ChunkData {
+        let mut chunk = ChunkData::from_storage(PaletteStorage::Single(Block::AIR));
+        if let Some(floor) = floor {
+            for (x, z) in ChunkData::slice() {
+                chunk.set_unchecked(IVec3::new(x as i32, 0, z as i32), floor);
+            }
+        }
+        chunk.sky = sky;
+        chunk.generated = true;
+        chunk
+    }
+
+    fn light(manager: &ChunkManager, channel: Channel, pos: IVec3) -> u8 {
+        channel.get(manager.get_light(pos).unwrap())
+    }
+
+    #[test]
+    fn sun_light_goes_straight_down() {
+        let registry = BlockRegistry::load("assets/blocks.ron").unwrap();
+        let stone = registry.by_name("stone").unwrap();
+
+        // Open sky above a chunk with a floor, lit from the bottom up
+        let mut manager = ChunkManager::default();
+        manager.chunks.insert(IVec3::ZERO, chunk(SkyColumns::ALL, Some(stone)));
+        manager.chunks.insert(IVec3::Y, chunk(SkyColumns::ALL, None));
+        light_chunk(&mut manager, &registry, IVec3::ZERO);
+        light_chunk(&mut manager, &registry, IVec3::Y);
+
+        for (x, y, z) in ChunkData::all().filter(|&(_, y, _)| y > 0) {
+            let pos = IVec3::new(x as i32, y as i32, z as i32);
+            assert_eq!(light(&manager, Channel::Sun, pos), MAX_LIGHT, "{pos} is in the dark");
+        }
+        assert_eq!(light(&manager, Channel::Sun, IVec3::new(3, 0, 3)), 0, "the sun went through the floor");
+    }
+
+    #[test]
+    fn block_light_fades_with_distance() {
+        let registry = BlockRegistry::load("assets/blocks.ron").unwrap();
+        let lamp = registry.by_name("lamp").unwrap();
+        let emission = registry.get(lamp).emission;
+
+        let mut data = chunk(SkyColumns::NONE, None);
+        data.set_unchecked(IVec3::splat(8), lamp);
+        let mut manager = ChunkManager::default();
+        manager.chunks.insert(IVec3::ZERO, data);
+        light_chunk(&mut manager, &registry, IVec3::ZERO);
+
+        for (x, y, z) in ChunkData::all() {
+            let pos = IVec3::new(x as i32, y as i32, z as i32);
+            let distance = (pos - IVec3::splat(8)).abs().to_array().iter().sum::<i32>() as u8;
+            assert_eq!(light(&manager, Channel::Block, pos), emission.saturating_sub(distance), "wrong light at {pos}");
+            assert_eq!(light(&manager, Channel::Sun, pos), 0);
+        }
+    }
+
+    #[test]
+    fn placed_blocks_cast_shadows() {
+        let registry = BlockRegistry::load("assets/blocks.ron").unwrap();
+        let stone = registry.by_name("stone").unwrap();
+
+        let mut manager = ChunkManager::default();
+        manager.chunks.insert(IVec3::ZERO, chunk(SkyColumns::ALL, Some(stone)));
+        light_chunk(&mut manager, &registry, IVec3::ZERO);
+
+        let pos = IVec3::new(8, 10, 8);
+        manager.set_block(pos, stone);
+        update_light(&mut manager, &registry, pos);
+        assert_eq!(light(&manager, Channel::Sun, pos), 0);
+        // The column under the block is only lit from the side
+        for y in 1..10 {
+            assert_eq!(light(&manager, Channel::Sun, IVec3::new(8, y, 8)), MAX_LIGHT - 1, "wrong light at height {y}");
+        }
+
+        manager.set_block(pos, Block::AIR);
+        update_light(&mut manager, &registry, pos);
+        for y in 1..=10 {
+            assert_eq!(light(&manager, Channel::Sun, IVec3::new(8, y, 8)), MAX_LIGHT, "no sun at height {y} once the block is removed");
+        }
+    }
+
+    #[test]
+    fn light_crosses_chunk_borders() {
+        let registry = BlockRegistry::load("assets/blocks.ron").unwrap();
+        let lamp = registry.by_name("lamp").unwrap();
+        let emission = registry.get(lamp).emission;
+        let source = IVec3::new(CHUNK_SIZE as i32 - 1, 8, 8);
+
+        // Either chunk may be lit first
+        for order in [[IVec3::ZERO, IVec3::X], [IVec3::X, IVec3::ZERO]] {
+            let mut manager = ChunkManager::default();
+            let mut data = chunk(SkyColumns::NONE, None);
+            data.set_unchecked(source, lamp);
+            manager.chunks.insert(IVec3::ZERO, data);
+            manager.chunks.insert(IVec3::X, chunk(SkyColumns::NONE, None));
+            for key in order {
+                light_chunk(&mut manager, &registry, key);
+            }
+
+            for distance in 1..=4 {
+                let pos = source + IVec3::new(distance, 0, 0);
+                assert_eq!(light(&manager, Channel::Block, pos), emission - distance as u8, "wrong light at {pos} lighting {order:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn surface_chunks_are_lit_before_the_chunk_above_them() {
+        let registry = BlockRegistry::load("assets/blocks.ron").unwrap();
+        let (noise, caves, biomes, blocks) = generator(7);
+
+        // The highest chunk crossing the surface, generated without anything above it
+        let generate = |key| generate_chunk(key, &noise, &caves, &biomes, &blocks);
+        let (key, data) = (0..16)
+            .rev()
+            .map(|y| (IVec3::new(0, y, 0), generate(IVec3::new(0, y, 0))))
+            .find(|(_, data)| data.single_block().is_none() && data.sky != SkyColumns::NONE)
+            .expect("no chunk crosses the surface");
+        let sky = data.sky;
+
+        let mut manager = ChunkManager::default();
+        manager.chunks.insert(key, data);
+        light_chunk(&mut manager, &registry, key);
+
+        // The sun shines straight down every column above the terrain, up to its surface
+        let origin = key * CHUNK_SIZE as i32;
+        for (x, z) in ChunkData::slice().filter(|&(x, z)| sky.get(x, z)) {
+            let column = (0..CHUNK_SIZE as i32).rev().map(|y| origin + IVec3::new(x as i32, y, z as i32));
+            for pos in column.take_while(|&pos| registry.get(manager.get_block(pos).unwrap()).transparent()) {
+                assert_eq!(Channel::Sun.get(manager.get_light(pos).unwrap()), MAX_LIGHT, "{pos} is in the dark");
+            }
+        }
+
+        // A roof over open columns of the chunk, in the chunk above generated afterwards
+        let stone = registry.by_name("stone").unwrap();
+        let open = |x: usize, z: usize| sky.get(x, z) && manager.get_block(origin + IVec3::new(x as i32, CHUNK_SIZE as i32 - 1, z as i32)) == Some(Block::AIR);
+        let (x, z) = ChunkData::slice()
+            .filter(|&(x, z)| (1..CHUNK_SIZE - 2).contains(&x) && (1..CHUNK_SIZE - 1).contains(&z))
+            .find(|&(x, z)| (x - 1..=x + 2).cartesian_product(z - 1..=z + 1).all(|(x, z)| open(x, z)))
+            .expect("no open columns to put a roof over");
+
+        let above = key + IVec3::Y;
+        let mut roof = generate(above);
+        for (i, j) in (x - 1..=x + 1).cartesian_product(z - 1..=z + 1) {
+            roof.set_unchecked(IVec3::new(i as i32, 0, j as i32), stone);
+        }
+        manager.chunks.insert(above, roof);
+        light_chunk(&mut manager, &registry, above);
+
+        // The column under the middle of the roof only gets the light coming from the side, the next one out keeps the sun
+        let under = origin + IVec3::new(x as i32, CHUNK_SIZE as i32 - 1, z as i32);
+        assert_eq!(Channel::Sun.get(manager.get_light(under).unwrap()), MAX_LIGHT - 2, "{under} is still in the sun");
+        let beside = under + IVec3::new(2, 0, 0);
+        assert_eq!(Channel::Sun.get(manager.get_light(beside).unwrap()), MAX_LIGHT, "{beside} lost the sun");
+    }
+}
@@ -1,31 +1,66 @@
 use std::f32::consts::PI;
 
-use bevy::{prelude::*, render::render_resource::PrimitiveTopology, time::FixedTimestep};
-use bevy_inspector_egui::{RegisterInspectable, WorldInspectorPlugin};
+use bevy::{prelude::*, time::FixedTimestep};
+use bevy_inspector_egui::RegisterInspectable;
 
+mod biome;
 mod block;
 mod chunk;
+mod config;
+mod falling;
+mod fluid;
+mod light;
 mod manager;
+mod material;
+mod mesher;
+mod noise_graph;
+mod occlusion;
+mod ore;
+mod physics;
 mod player;
+mod preview;
+mod raycast;
+mod region;
+mod storage;
+mod structure;
+mod terrain;
+#[cfg(test)]
+mod test_util;
+mod tick;
 
-use chunk::{generate_mesh, generate_terrain, NeedsMesh, cull_meshes};
-use manager::{load_chunks, unload_chunks, ChunkManager, CleanupTimer};
+use biome::Biomes;
+use block::BlockRegistry;
+use chunk::{apply_meshes, apply_terrain, generate_mesh, generate_terrain, cull_meshes, sort_translucent, TaskBudget};
+use config::{Args, WorldGenConfig};
+use fluid::FluidTable;
+use light::light_chunks;
+use manager::{load_chunks, process_load_queue, unload_chunks, memory_report, remesh_chunks, ChunkManager, CleanupTimer, LoadLimits, LoadQueue};
+use material::ChunkMaterial;
+use mesher::{toggle_mesher, Mesher};
 use noise::OpenSimplex;
-use player::{BoundingBox, Velocity, VelocityMask};
+use ore::OreTable;
+use region::{autosave, save_on_exit, WorldSave, AUTOSAVE_SECONDS};
+use structure::StructureBlocks;
+use terrain::TerrainBlocks;
+use tick::{TickSettings, WorldTick};
+use physics::{FollowBody, VoxelBodyBundle};
+use player::{Player, SelectedBlock, Velocity, VelocityMask, EYE_OFFSET};
 
-#[derive(Resource)]
+#[derive(Resource, Clone)]
 pub struct Noise(OpenSimplex);
 
 #[derive(Default, Resource)]
 pub struct AtlasImage {
     image: Handle<Image>,
-    material: Handle<StandardMaterial>,
+    material: Handle<ChunkMaterial>,
+    cutout: Handle<ChunkMaterial>,
+    translucent: Handle<ChunkMaterial>,
 }
 
 fn startup(
     mut commands: Commands,
     server: Res<AssetServer>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut materials: ResMut<Assets<ChunkMaterial>>,
     mut atlas: ResMut<AtlasImage>,
 ) {
     commands.spawn(DirectionalLightBundle {
@@ -40,6 +75,12 @@ fn startup(
         ..default()
     });
 
+    let player = commands.spawn((
+        VoxelBodyBundle::new(Vec3::new(0.0, 80.0, 0.0) - EYE_OFFSET, Vec3::new(0.8, 1.9, 0.8)),
+        Player,
+        Name::new("Player"),
+    )).id();
+
     commands.spawn((
         Camera3dBundle {
             transform: Transform::from_xyz(0.0, 80.0, 0.0),
@@ -49,19 +90,19 @@ fn startup(
             }),
             ..default()
         },
-        Velocity(Vec3::ZERO),
-        VelocityMask(Vec3::ONE),
-        BoundingBox::from_size(Vec3::new(0.8, 1.9, 0.8)),
+        FollowBody { body: player, offset: EYE_OFFSET },
     ));
 
     atlas.image = server.load("atlas.png");
-    atlas.material = materials.add(atlas.image.clone().into());
+    atlas.material = materials.add(ChunkMaterial::opaque(atlas.image.clone()));
+    atlas.cutout = materials.add(ChunkMaterial::cutout(atlas.image.clone()));
+    atlas.translucent = materials.add(ChunkMaterial::translucent(atlas.image.clone()));
 }
 
 fn fix_atlas_filtering(
     mut events: EventReader<AssetEvent<Image>>,
     mut images: ResMut<Assets<Image>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut materials: ResMut<Assets<ChunkMaterial>>,
     atlas: Res<AtlasImage>,
 ) {
     for event in events.iter() {
@@ -71,23 +112,70 @@ fn fix_atlas_filtering(
                 let image = images.get_mut(handle).unwrap();
                 image.sampler_descriptor = bevy::render::texture::ImageSampler::nearest();
 
-                *materials.get_mut(&atlas.material).unwrap() = atlas.image.clone().into(); // regenerate material to pass sampler for some reason
+                // regenerate materials to pass sampler for some reason
+                *materials.get_mut(&atlas.material).unwrap() = ChunkMaterial::opaque(atlas.image.clone());
+                *materials.get_mut(&atlas.cutout).unwrap() = ChunkMaterial::cutout(atlas.image.clone());
+                *materials.get_mut(&atlas.translucent).unwrap() = ChunkMaterial::translucent(atlas.image.clone());
             }
         }
     }
 }
 
 fn main() {
+    let args = Args::parse(std::env::args().skip(1)).unwrap_or_else(|e| panic!("{e}"));
+    let config = if args.heightmap.is_some() {
+        WorldGenConfig::from_args(&args)
+    } else {
+        WorldGenConfig::for_world(&args)
+    };
+    let config = config.unwrap_or_else(|e| panic!("{e}"));
+    let noise = Noise(OpenSimplex::new(config.seed.value()));
+
+    let registry = BlockRegistry::load("assets/blocks.ron").unwrap_or_else(|e| panic!("{e}"));
+    let stone = registry.by_name("stone").unwrap();
+    let terrain_blocks = TerrainBlocks::from_registry(&registry).unwrap_or_else(|e| panic!("{e}"));
+    let biomes = Biomes::from_registry(&registry, config.heightmap.build(config.seed.value()), &config.filler_depths).unwrap_or_else(|e| panic!("{e}"));
+    let structure_blocks = StructureBlocks::from_registry(&registry).unwrap_or_else(|e| panic!("{e}"));
+    let ores = OreTable::load("assets/ores.ron", &registry).unwrap_or_else(|e| panic!("{e}"));
+    let fluids = FluidTable::load("assets/fluids.ron", &registry).unwrap_or_else(|e| panic!("{e}"));
+
+    if let Some(path) = &args.heightmap {
+        preview::render_heightmap(&noise, &biomes, args.size, path).unwrap_or_else(|e| panic!("could not render the heightmap: {e}"));
+        return;
+    }
+
+    let save = WorldSave::new(args.world);
+    let manager = ChunkManager {
+        pending: save.load_pending(&registry).unwrap_or_else(|e| panic!("could not load the world: {e}")),
+        ..default()
+    };
+
     App::new()
-        .insert_resource(Noise(OpenSimplex::new(102)))
+        .insert_resource(noise)
+        .insert_resource(registry)
+        .insert_resource(terrain_blocks)
+        .insert_resource(biomes)
+        .insert_resource(structure_blocks)
+        .insert_resource(ores)
+        .insert_resource(fluids)
+        .insert_resource(WorldTick::default())
+        .insert_resource(TickSettings { random_ticks: args.random_ticks })
+        .insert_resource(config)
         .insert_resource(player::CameraDisabled(true))
-        .insert_resource(ChunkManager::default())
+        .insert_resource(SelectedBlock(stone))
+        .insert_resource(manager)
+        .insert_resource(save)
         .insert_resource(AtlasImage { ..default() })
+        .insert_resource(Mesher::default())
+        .insert_resource(TaskBudget::default())
+        .insert_resource(LoadQueue::default())
+        .insert_resource(LoadLimits::default())
         .insert_resource(CleanupTimer(Timer::from_seconds(0.5, TimerMode::Repeating)))
 
         .add_plugins(DefaultPlugins)
-        // .add_plugin(WorldInspectorPlugin::default())
-        .add_plugin(bevy::diagnostic::FrameTimeDiagnosticsPlugin::default())
+        .add_plugin(MaterialPlugin::<ChunkMaterial>::default())
+        // .add_plugin(bevy_inspector_egui::WorldInspectorPlugin::default())
+        .add_plugin(bevy::diagnostic::FrameTimeDiagnosticsPlugin)
         .add_plugin(bevy::diagnostic::LogDiagnosticsPlugin::default())
 
         .register_inspectable::<Velocity>()
@@ -96,17 +184,48 @@ fn main() {
         .add_system(fix_atlas_filtering)
         // Player systems
         .add_system(player::rotate_camera)
-        .add_system(player::move_camera)
-        .add_system(player::collision.before(player::move_camera))
+        .add_system(player::move_player.before(physics::step_bodies))
+        .add_system(player::interact.before(light_chunks))
+        .add_system(physics::follow_bodies.after(physics::step_bodies))
+        // Falling block systems
+        .add_system(falling::spawn_falling_blocks)
+        .add_system(falling::land_blocks.after(physics::step_bodies).before(light_chunks))
+        .add_system(falling::despawn_items)
         //Chunk systems
+        .add_system(process_load_queue.before(generate_terrain))
         .add_system(generate_terrain)
+        .add_system(apply_terrain.before(light_chunks))
+        .add_system(light_chunks.before(generate_mesh))
+        .add_system(remesh_chunks.after(light_chunks))
         .add_system(generate_mesh)
+        .add_system(apply_meshes)
         .add_system(cull_meshes)
+        .add_system(sort_translucent.after(apply_meshes))
+        .add_system(memory_report)
+        .add_system(player::report_biome)
+        .add_system(toggle_mesher)
+        .add_system_to_stage(CoreStage::Last, save_on_exit)
         .add_system_set(
             SystemSet::new()
                 .with_run_criteria(FixedTimestep::steps_per_second(5.0))
                 .with_system(load_chunks)
                 .with_system(unload_chunks)
         )
+        .add_system_set(
+            SystemSet::new()
+                .with_run_criteria(FixedTimestep::steps_per_second(physics::STEPS_PER_SECOND).with_label(physics::PHYSICS_TIMESTEP))
+                .with_system(fluid::buoyancy.before(physics::step_bodies))
+                .with_system(physics::step_bodies)
+        )
+        .add_system_set(
+            SystemSet::new()
+                .with_run_criteria(FixedTimestep::steps_per_second(tick::TICKS_PER_SECOND))
+                .with_system(tick::tick_blocks.before(light_chunks))
+        )
+        .add_system_set(
+            SystemSet::new()
+                .with_run_criteria(FixedTimestep::step(AUTOSAVE_SECONDS))
+                .with_system(autosave)
+        )
         .run()
 }
@@ -1,15 +1,27 @@
-use std::time::{Instant, Duration};
+use std::{collections::VecDeque, marker::PhantomData, time::{Instant, Duration}};
 
-use bevy::{prelude::*, utils::HashMap, render::view::NoFrustumCulling};
+use bevy::{ecs::system::SystemParam, pbr::NotShadowCaster, prelude::*, utils::{HashMap, HashSet}, render::view::NoFrustumCulling};
 use itertools::Itertools;
 
-use crate::{chunk::{NeedsMesh, NeedsTerrain, Chunk}, AtlasImage, block::Block};
+use crate::{chunk::{camera_frustum, chunk_in_frustum, NeedsMesh, NeedsTerrain, Chunk, LayerMeshes}, mesher::MeshData, AtlasImage, block::{Block, BlockRegistry, BlockRender, Face}, storage::PaletteStorage, region::WorldSave, light::{Channel, LightData, SkyColumns}, occlusion::Connectivity, structure::PendingStructures, tick::{TickQueue, WorldTick}};
 
 #[derive(Default, Resource)]
 pub struct ChunkManager {
     pub chunks: HashMap<IVec3, ChunkData>,
     /// List of create meshes and their respective lod
-    pub meshes: HashMap<IVec3, (Entity, u32)>
+    pub meshes: HashMap<IVec3, (Entity, u32)>,
+    /// Chunks whose mesh needs to be regenerated
+    pub remesh: HashSet<IVec3>,
+    /// Positions of the blocks that changed since the light was last updated
+    pub light_updates: Vec<IVec3>,
+    /// Positions of the blocks that changed since the last world tick
+    pub block_updates: Vec<IVec3>,
+    /// Blocks that started falling since the last frame, turned into entities by `falling::spawn_falling_blocks`
+    pub falling_blocks: Vec<(IVec3, Block)>,
+    /// Blocks placed by structures into neighbouring chunks
+    pub pending: PendingStructures,
+    /// Which faces of each meshed chunk can see each other, used for occlusion culling
+    pub connectivity: HashMap<IVec3, Connectivity>,
 }
 
 impl ChunkManager {
@@ -38,6 +50,79 @@ impl ChunkManager {
         }
     }
 
+    /// Gives the light in the given chunk, or in an adjacent chunk if pos is out of bounds
+    /// @returns None if the adjacent chunk isn't generated
+    pub fn light_with_adjacent(&self, key: IVec3, pos: IVec3) -> Option<u8> {
+        let (offset, pos) = Self::get_keys(pos);
+        let chunk = self.chunks.get(&(key + offset))?;
+
+        chunk.generated.then(|| chunk.get_light(pos))
+    }
+
+    /// @returns The block at the given global position, or None if its chunk isn't generated
+    pub fn get_block(&self, global_pos: IVec3) -> Option<Block> {
+        let (key, pos) = Self::get_keys(global_pos);
+        let chunk = self.chunks.get(&key)?;
+
+        chunk.generated.then(|| chunk.get_unchecked(pos))
+    }
+
+    /// @returns The light at the given global position, or None if its chunk isn't generated
+    pub fn get_light(&self, global_pos: IVec3) -> Option<u8> {
+        self.light_with_adjacent(IVec3::ZERO, global_pos)
+    }
+
+    /// Replaces the block at the given global position, marking its chunk as modified.
+    /// The chunk is remeshed, along with the neighbouring chunks touching the block.
+    /// @returns The previous block, or None if the chunk isn't generated
+    pub fn set_block(&mut self, global_pos: IVec3, block: Block) -> Option<Block> {
+        let previous = self.replace_block(global_pos, block)?;
+        if previous != block {
+            self.chunks.get_mut(&Self::get_keys(global_pos).0).unwrap().dirty = true;
+        }
+        Some(previous)
+    }
+
+    /// Replaces the block at the given global position like `set_block`, without marking its chunk as modified,
+    /// for blocks that generating the chunk again gives back
+    /// @returns The previous block, or None if the chunk isn't generated
+    pub fn replace_block(&mut self, global_pos: IVec3, block: Block) -> Option<Block> {
+        let (key, pos) = Self::get_keys(global_pos);
+        let chunk = self.chunks.get_mut(&key).filter(|c| c.generated)?;
+
+        let previous = chunk.get_unchecked(pos);
+        if previous == block {
+            return Some(previous);
+        }
+        chunk.set_unchecked(pos, block);
+
+        // Faces and ambient occlusion of the neighbours of the block may have changed, including across chunk borders
+        for ((x, y), z) in (-1..=1).cartesian_product(-1..=1).cartesian_product(-1..=1) {
+            let (neighbour, _) = Self::get_keys(global_pos + IVec3::new(x, y, z));
+            self.remesh.insert(neighbour);
+        }
+        self.light_updates.push(global_pos);
+        self.block_updates.push(global_pos);
+
+        Some(previous)
+    }
+
+    /// Schedules an update of the block at the given global position, see `TickQueue::schedule`
+    pub fn schedule_tick(&mut self, global_pos: IVec3, due: u64) {
+        let (key, pos) = Self::get_keys(global_pos);
+        if let Some(chunk) = self.chunks.get_mut(&key).filter(|c| c.generated) {
+            chunk.ticks.schedule(pos, due);
+            chunk.dirty = true;
+        }
+    }
+
+    pub fn set_light(&mut self, global_pos: IVec3, light: u8) {
+        let (key, pos) = Self::get_keys(global_pos);
+        if let Some(chunk) = self.chunks.get_mut(&key) {
+            chunk.set_light(pos, light);
+        }
+    }
+
     /// @returns A tuple of the key of the key of the chunk and the position inside the chunk
     pub fn get_keys(global_pos: IVec3) -> (IVec3, IVec3) {
         let key = IVec3::new(
@@ -70,30 +155,57 @@ impl ChunkManager {
         self.meshes.contains_key(&key)
     }
 
-    /// @returns An iterator to all valid adjacent chunks keys
+    /// @returns An iterator to all adjacent chunks keys
     pub fn adjacent_keys(key: IVec3) -> impl Iterator<Item = IVec3> {
         (-1..=1)
             .cartesian_product(-1..=1)
             .cartesian_product(-1..=1)
             .map(move |((x, y), z)| IVec3::new(x, y, z) + key)
             .filter(move |v| v != &key)
-            .filter(|&v| Self::in_world_range(v))
     }
 
-    /// Checks if a chunk key is valid in the world
-    pub fn in_world_range(key: IVec3) -> bool {
-        (0..WORLD_HEIGHT).contains(&key.y)
+    /// Copies a chunk and its neighbours along with the lod of their meshes, so that it can be meshed away from the main thread
+    pub fn snapshot(&self, key: IVec3) -> ChunkManager {
+        let chunks = Self::adjacent_keys(key)
+            .chain([key])
+            .filter_map(|k| Some((k, self.chunks.get(&k)?.clone())))
+            .collect();
+
+        let meshes = Self::adjacent_keys(key)
+            .chain([key])
+            .filter_map(|k| Some((k, *self.meshes.get(&k)?)))
+            .collect();
+
+        ChunkManager { chunks, meshes, ..default() }
+    }
+
+    /// @returns A copy of every chunk downsampled to the given lod, see `ChunkData::downsample`
+    pub fn downsample(&self, lod: u32, registry: &BlockRegistry) -> ChunkManager {
+        let chunks = self.chunks.iter().map(|(&k, c)| (k, c.downsample(lod, registry))).collect();
+
+        ChunkManager { chunks, meshes: self.meshes.clone(), ..default() }
     }
 }
 
 pub const CHUNK_SIZE: usize = 16;
-/// Number of chunks constituting the world vertically
-pub const WORLD_HEIGHT: i32 = 128 / CHUNK_SIZE as i32;
 
-#[derive(Default)]
+/// Number of blocks in a chunk
+pub const CHUNK_VOLUME: usize = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
+
+#[derive(Default, Clone)]
 pub struct ChunkData {
-    pub data: [[[Block; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE],
+    blocks: PaletteStorage,
     pub generated: bool,
+    /// Columns of the chunk above the terrain, lit by the sun from above
+    pub sky: SkyColumns,
+    /// Sun and block light of every block, computed once the chunk is generated
+    pub light: LightData,
+    /// Wether the light of the chunk was computed
+    pub lit: bool,
+    /// Block updates scheduled in this chunk
+    pub ticks: TickQueue,
+    /// Wether the chunk was modified since it was last saved
+    pub dirty: bool,
     pub cached_time: Option<Instant>
 }
 
@@ -104,9 +216,55 @@ macro_rules! decompose_vec_into {
 }
 
 impl ChunkData {
+    pub fn from_storage(blocks: PaletteStorage) -> Self {
+        Self { blocks, ..default() }
+    }
+
+    pub fn blocks(&self) -> &PaletteStorage {
+        &self.blocks
+    }
+
+    #[inline]
+    fn index(x: usize, y: usize, z: usize) -> usize {
+        x + y * CHUNK_SIZE + z * CHUNK_SIZE * CHUNK_SIZE
+    }
+
     pub fn get_unchecked(&self, p: IVec3) -> Block {
         let (x, y, z) = decompose_vec_into!(p, usize);
-        self.data[z][y][x]
+        self.blocks.get(Self::index(x, y, z))
+    }
+
+    pub fn set_unchecked(&mut self, p: IVec3, block: Block) {
+        let (x, y, z) = decompose_vec_into!(p, usize);
+        self.blocks.set(CHUNK_VOLUME, Self::index(x, y, z), block);
+    }
+
+    pub fn get_light(&self, p: IVec3) -> u8 {
+        let (x, y, z) = decompose_vec_into!(p, usize);
+        self.light.get(Self::index(x, y, z))
+    }
+
+    pub fn set_light(&mut self, p: IVec3, light: u8) {
+        let (x, y, z) = decompose_vec_into!(p, usize);
+        self.light.set(Self::index(x, y, z), light);
+    }
+
+    /// Shrinks the block palette to the blocks actually present in the chunk
+    pub fn compact(&mut self) {
+        self.blocks.compact(CHUNK_VOLUME);
+    }
+
+    /// @returns Some(block) if the chunk is entirely made of this block
+    pub fn single_block(&self) -> Option<Block> {
+        match self.blocks {
+            PaletteStorage::Single(block) => Some(block),
+            _ => None
+        }
+    }
+
+    /// @returns The number of bytes used by this chunk's blocks, light and sky columns
+    pub fn memory_usage(&self) -> usize {
+        self.blocks.memory_usage() + self.light.memory_usage() + std::mem::size_of::<SkyColumns>()
     }
 
     pub fn get(&self, p: IVec3) -> Option<Block> {
@@ -131,7 +289,7 @@ impl ChunkData {
     }
 
     pub fn all_blocks(&self) -> impl Iterator<Item = (Block, usize, usize, usize)> + '_ {
-        Self::all().map(|(x, y, z)| (self.data[z][y][x], x, y, z))
+        Self::all().map(|(x, y, z)| (self.blocks.get(Self::index(x, y, z)), x, y, z))
     }
 
     #[inline]
@@ -141,83 +299,242 @@ impl ChunkData {
         base.clone().cartesian_product(base)
     }
 
-    /// @returns An iterator over 3 dimensions of the chunk, with xz distorted by the given lod
+    /// @returns An iterator over the first block of every cell of the given lod
     #[inline]
     pub fn all_lod(lod: u32) -> impl Iterator<Item = (usize, usize, usize)> {
         Self::slice_lod(lod)
-            .cartesian_product(0..CHUNK_SIZE)
+            .cartesian_product((0..CHUNK_SIZE).step_by(2usize.pow(lod)))
             .map(|((x, z), y)| (x, y, z))
     }
 
     pub fn all_blocks_lod(&self, lod: u32) -> impl Iterator<Item = (Block, usize, usize, usize)> + '_ {
-        Self::all_lod(lod).map(|(x, y, z)| (self.data[z][y][x], x, y, z))
+        Self::all_lod(lod).map(|(x, y, z)| (self.blocks.get(Self::index(x, y, z)), x, y, z))
+    }
+
+    /// @returns A copy of the chunk where every cell of the given lod is filled with a single representative block.
+    /// A cell is made of its highest visible block if at least half of it is visible, and of air otherwise,
+    /// so that surfaces keep their top block and thin overhangs vanish instead of growing.
+    /// The light of a cell is the brightest light found inside of it, channel by channel.
+    pub fn downsample(&self, lod: u32, registry: &BlockRegistry) -> ChunkData {
+        let size = 2i32.pow(lod);
+        let mut data = ChunkData {
+            blocks: self.blocks.clone(),
+            light: self.light.clone(),
+            generated: self.generated,
+            sky: self.sky,
+            lit: self.lit,
+            ..default()
+        };
+        if lod == 0 {
+            return data;
+        }
+
+        for (x, y, z) in Self::all_lod(lod) {
+            let origin = IVec3::new(x as i32, y as i32, z as i32);
+            // From the top layer down, so that the first visible block found is the highest
+            let cell = (0..size)
+                .rev()
+                .cartesian_product(0..size)
+                .cartesian_product(0..size)
+                .map(|((y, z), x)| origin + IVec3::new(x, y, z))
+                .collect_vec();
+
+            let mut visible = 0;
+            let mut top = None;
+            let mut light = 0;
+            for &pos in &cell {
+                let block = self.get_unchecked(pos);
+                if registry.get(block).render != BlockRender::Invisible {
+                    visible += 1;
+                    top.get_or_insert(block);
+                }
+                let level = self.get_light(pos);
+                for channel in Channel::ALL {
+                    light = channel.with(light, channel.get(light).max(channel.get(level)));
+                }
+            }
+
+            let block = if visible * 2 >= cell.len() { top.unwrap_or(Block::AIR) } else { Block::AIR };
+            for pos in cell {
+                data.set_unchecked(pos, block);
+                data.set_light(pos, light);
+            }
+        }
+
+        data.compact();
+        data
     }
 }
 
 // Specified at half size
 const RENDER_DISTANCE: i32 = 16;
+const VERTICAL_RENDER_DISTANCE: i32 = 4;
+/// Half size of the box of chunks kept loaded around the player
+const LOAD_DISTANCE: IVec3 = IVec3::new(RENDER_DISTANCE, VERTICAL_RENDER_DISTANCE, RENDER_DISTANCE);
 const LOD_RANGE: i32 = 4;
+
+/// @returns The key of the chunk the player is in
+pub fn player_key(transform: &Transform) -> IVec3 {
+    ChunkManager::get_keys(transform.translation.floor().as_ivec3()).0
+}
+
+/// Chunks that need to be loaded or whose lod changed, the most important first
+#[derive(Resource, Default)]
+pub struct LoadQueue {
+    queue: VecDeque<(IVec3, u32)>,
+}
+
+/// Maximum number of chunks taken from the load queue each frame
+#[derive(Resource)]
+pub struct LoadLimits {
+    /// Chunks loaded from disk or sent to terrain generation
+    pub terrain: usize,
+    /// Chunks sent to meshing because their lod changed
+    pub meshes: usize,
+}
+
+impl Default for LoadLimits {
+    fn default() -> Self {
+        Self { terrain: 32, meshes: 32 }
+    }
+}
+
+/// Finds the chunks around the player that need to be loaded or remeshed, and queues them.
+/// The chunk the player stands in comes first, then the chunks in view, each sorted by distance.
 pub fn load_chunks(
-    mut commands: Commands,
     mut manager: ResMut<ChunkManager>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    atlas: Res<AtlasImage>,
-    player: Query<&Transform, With<Camera>>,
+    mut queue: ResMut<LoadQueue>,
+    player: Query<(&Transform, &Projection), With<Camera>>,
 ) {
-    let player = player.single();
-    let mut player_key = (player.translation / CHUNK_SIZE as f32).as_ivec3();
-    player_key.y = 0;
+    let (transform, projection) = player.single();
+    let frustum = camera_frustum(transform, projection);
 
-    for ((i, j), k) in (-RENDER_DISTANCE..=RENDER_DISTANCE).cartesian_product(0..WORLD_HEIGHT).cartesian_product(-RENDER_DISTANCE..=RENDER_DISTANCE) {
+    let player_key = player_key(transform);
+
+    let mut pending = Vec::new();
+    let ranges = (-LOAD_DISTANCE.x..=LOAD_DISTANCE.x)
+        .cartesian_product(-LOAD_DISTANCE.y..=LOAD_DISTANCE.y)
+        .cartesian_product(-LOAD_DISTANCE.z..=LOAD_DISTANCE.z);
+    for ((i, j), k) in ranges {
         let key = IVec3::new(i, j, k) + player_key;
-        if !ChunkManager::in_world_range(key) {
-            continue;
-        }
 
         if let Some(chunk) = manager.chunks.get_mut(&key) {
             chunk.cached_time = None;
         }
-        else {
-            manager.chunks.insert(
-                key,
-                ChunkData {
-                    generated: false,
-                    data: default(),
-                    cached_time: None
-                },
-            );
-        }
 
-        let lod = (i / LOD_RANGE).abs().max((k / LOD_RANGE).abs()) as u32;
+        let lod = (i / LOD_RANGE).abs().max((j / LOD_RANGE).abs()).max((k / LOD_RANGE).abs()) as u32;
         let lod = lod.min((CHUNK_SIZE as f32).log2() as u32); // limit lod level to chunk size
 
+        if manager.meshes.get(&key).is_some_and(|&(_, loaded_lod)| loaded_lod == lod) {
+            continue;
+        }
+
+        let hidden = key != player_key && !chunk_in_frustum(&frustum, key);
+        let priority = (hidden, (key - player_key).as_vec3().length_squared() as i32);
+        pending.push((priority, key, lod));
+    }
+
+    pending.sort_unstable_by_key(|&(priority, ..)| priority);
+    queue.queue = pending.into_iter().map(|(_, key, lod)| (key, lod)).collect();
+}
+
+/// Resources chunks are loaded with, either from the save or as new chunks to generate
+#[derive(SystemParam)]
+pub struct ChunkSources<'w, 's> {
+    limits: Res<'w, LoadLimits>,
+    save: Res<'w, WorldSave>,
+    registry: Res<'w, BlockRegistry>,
+    tick: Res<'w, WorldTick>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+/// Loads the chunks at the front of the load queue, generating them when they aren't saved on disk
+pub fn process_load_queue(
+    mut commands: Commands,
+    mut manager: ResMut<ChunkManager>,
+    mut queue: ResMut<LoadQueue>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    atlas: Res<AtlasImage>,
+    sources: ChunkSources,
+) {
+    let ChunkSources { limits, save, registry, tick, .. } = sources;
+    let (mut terrain, mut remeshed) = (0, 0);
+
+    while let Some(&(key, lod)) = queue.queue.front() {
         if let Some((entity, loaded_lod)) = manager.meshes.get_mut(&key) {
+            if remeshed >= limits.meshes {
+                break;
+            }
+            queue.queue.pop_front();
+
             if *loaded_lod == lod { continue; }
 
-            // eprintln!("Recreating mesh of {key}");
             commands.entity(*entity).insert(NeedsMesh(lod));
             *loaded_lod = lod;
+            remeshed += 1;
+
+            // Neighbours draw the faces on their border depending on the lod of this chunk
+            for face in Face::ALL {
+                manager.remesh.insert(key + face.normal());
+            }
+            continue;
         }
-        else {
-            let mut entity = commands
-                .spawn((
-                    Chunk { key },
-                    NeedsMesh(lod),
-                    PbrBundle {
-                        mesh: meshes.add(Mesh::new(bevy::render::render_resource::PrimitiveTopology::TriangleList)),
-                        material: atlas.material.clone(),
-                        transform: Transform::from_translation(key.as_vec3() * CHUNK_SIZE as f32),
-                        ..default()
-                    },
-                    NoFrustumCulling,
-                    Name::new(format!("{key}"))
-                ));
-
-            if !manager.is_generated(key) {
-                entity.insert(NeedsTerrain);
+
+        if terrain >= limits.terrain {
+            break;
+        }
+        queue.queue.pop_front();
+        terrain += 1;
+
+        if !manager.is_loaded(key) {
+            // Chunks saved on disk don't need to be generated again
+            let data = match save.load_chunk(key, tick.0, &registry) {
+                Ok(Some(mut data)) => {
+                    // Structures of neighbours generated since this chunk was saved
+                    manager.apply_pending(&registry, key, &mut data);
+                    data
+                }
+                Ok(None) => ChunkData::default(),
+                Err(e) => {
+                    error!("Could not load chunk {key}: {e}");
+                    ChunkData::default()
+                }
+            };
+            manager.chunks.insert(key, data);
+        }
+
+        let cutout = meshes.add(MeshData::default().into_mesh());
+        let translucent = meshes.add(MeshData::with_drops().into_mesh());
+
+        let mut entity = commands
+            .spawn((
+                Chunk { key },
+                NeedsMesh(lod),
+                MaterialMeshBundle {
+                    mesh: meshes.add(MeshData::default().into_mesh()),
+                    material: atlas.material.clone(),
+                    transform: Transform::from_translation(key.as_vec3() * CHUNK_SIZE as f32),
+                    ..default()
+                },
+                LayerMeshes { cutout: cutout.clone(), translucent: translucent.clone(), sorted_from: None },
+                NoFrustumCulling,
+                // Shadow passes expect a position attribute, which packed chunk meshes don't have
+                NotShadowCaster,
+                Name::new(format!("{key}"))
+            ));
+
+        // Cutout and translucent blocks are drawn by children with their own material, hidden along with the chunk
+        entity.with_children(|parent| {
+            for (mesh, material) in [(cutout, atlas.cutout.clone()), (translucent, atlas.translucent.clone())] {
+                parent.spawn((MaterialMeshBundle { mesh, material, ..default() }, NoFrustumCulling, NotShadowCaster));
             }
-            manager.meshes.insert(key, (entity.id(), lod));
+        });
+
+        if !manager.is_generated(key) {
+            entity.insert(NeedsTerrain);
         }
+        manager.meshes.insert(key, (entity.id(), lod));
     }
 }
 
@@ -227,34 +544,21 @@ pub struct CleanupTimer(pub Timer);
 pub fn unload_chunks(
     mut commands: Commands,
     mut manager: ResMut<ChunkManager>,
+    save: Res<WorldSave>,
+    tick: Res<WorldTick>,
     chunks: Query<(Entity, &Chunk)>,
     player: Query<&Transform, With<Camera>>,
 ) {
-    let player = player.single();
-    let mut player_key = (player.translation / CHUNK_SIZE as f32).as_ivec3();
-    player_key.y = 0;
+    let player_key = player_key(player.single());
 
     for (entity, chunk) in chunks.iter() {
         let relative_key = chunk.key - player_key;
 
-        if relative_key
-            .cmplt(IVec3::new(
-                -RENDER_DISTANCE,
-                0,
-                -RENDER_DISTANCE,
-            ))
-            .any()
-            || relative_key
-                .cmpgt(IVec3::new(
-                    RENDER_DISTANCE,
-                    WORLD_HEIGHT,
-                    RENDER_DISTANCE,
-                ))
-                .any()
-        {
+        if relative_key.abs().cmpgt(LOAD_DISTANCE).any() {
             manager.chunks.get_mut(&chunk.key).unwrap().cached_time = Some(Instant::now());
-            commands.entity(entity).despawn();
+            commands.entity(entity).despawn_recursive();
             manager.meshes.remove(&chunk.key);
+            manager.connectivity.remove(&chunk.key);
         }
     }
 
@@ -267,7 +571,49 @@ pub fn unload_chunks(
         }
     }
 
+    // Only the modified chunks being removed are written, the rest of the world waiting for the next full save
+    let dirty = remove.iter().copied().filter(|key| manager.chunks[key].dirty).collect_vec();
+    if let Err(e) = save.save_chunks(dirty.iter().map(|key| (*key, &manager.chunks[key])), tick.0) {
+        // Keep the chunks in memory rather than losing their modifications
+        error!("Could not save chunks: {e}");
+        return;
+    }
+
+    for key in dirty {
+        manager.mark_saved(key);
+    }
     for key in remove {
         manager.chunks.remove(&key);
     }
 }
+
+/// Logs how much memory chunk storage takes, compared to storing a full block array per chunk
+pub fn memory_report(manager: Res<ChunkManager>, keyboard: Res<Input<KeyCode>>) {
+    if !keyboard.just_pressed(KeyCode::F3) {
+        return;
+    }
+
+    let chunks = manager.chunks.len();
+    let single = manager.chunks.values().filter(|c| c.single_block().is_some()).count();
+    let cached = manager.chunks.values().filter(|c| c.cached_time.is_some()).count();
+    let palette_bytes: usize = manager.chunks.values().map(ChunkData::memory_usage).sum();
+    // Chunks used to be a full array of one byte blocks, along with the same arrays of light and sky columns
+    let array_bytes = chunks * (CHUNK_VOLUME * 2 + std::mem::size_of::<SkyColumns>());
+
+    info!(
+        "{chunks} chunks ({single} single block, {cached} cached): {:.2} MiB with palettes, {:.2} MiB with arrays ({:.1}%)",
+        palette_bytes as f64 / (1024.0 * 1024.0),
+        array_bytes as f64 / (1024.0 * 1024.0),
+        palette_bytes as f64 / array_bytes.max(1) as f64 * 100.0
+    );
+}
+
+/// Regenerates the meshes of the chunks that were modified
+pub fn remesh_chunks(mut commands: Commands, mut manager: ResMut<ChunkManager>) {
+    let remesh = std::mem::take(&mut manager.remesh);
+    for key in remesh {
+        if let Some(&(entity, lod)) = manager.meshes.get(&key) {
+            commands.entity(entity).insert(NeedsMesh(lod));
+        }
+    }
+}
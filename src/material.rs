@@ -0,0 +1,81 @@
+use bevy::{
+    pbr::{MaterialPipeline, MaterialPipelineKey},
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        mesh::{MeshVertexAttribute, MeshVertexBufferLayout},
+        render_resource::{
+            AsBindGroup, RenderPipelineDescriptor, ShaderRef, SpecializedMeshPipelineError,
+            VertexFormat,
+        },
+    },
+};
+
+/// Whole vertex of a chunk mesh packed in a single value, see `mesher::PackedVertex`
+pub const ATTRIBUTE_VOXEL: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_Voxel", 184_029_357, VertexFormat::Uint32);
+
+/// How far below its position a vertex is drawn, in sixteenths of a block.
+/// Only meshes holding fluids have it, see `mesher::MeshData::drops`.
+pub const ATTRIBUTE_FLUID_DROP: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_FluidDrop", 184_029_358, VertexFormat::Uint32);
+
+/// Material of chunk meshes, which decodes packed vertices and samples the block atlas with uvs repeating inside of each tile
+#[derive(AsBindGroup, TypeUuid, Debug, Clone)]
+#[uuid = "8f2c6b0e-4d1a-4c55-9a4e-1f6d3b7c2a90"]
+pub struct ChunkMaterial {
+    #[texture(0)]
+    #[sampler(1)]
+    pub atlas: Handle<Image>,
+    /// Pixels with a lower alpha are discarded
+    #[uniform(2)]
+    pub alpha_cutoff: f32,
+    pub alpha_mode: AlphaMode,
+}
+
+impl ChunkMaterial {
+    pub fn opaque(atlas: Handle<Image>) -> Self {
+        Self { atlas, alpha_cutoff: 0.0, alpha_mode: AlphaMode::Opaque }
+    }
+
+    /// Material of cutout blocks, whose texture is either fully opaque or fully transparent
+    pub fn cutout(atlas: Handle<Image>) -> Self {
+        Self { atlas, alpha_cutoff: 0.5, alpha_mode: AlphaMode::Mask(0.5) }
+    }
+
+    /// Material of translucent blocks, blended over what is behind them
+    pub fn translucent(atlas: Handle<Image>) -> Self {
+        Self { atlas, alpha_cutoff: 0.0, alpha_mode: AlphaMode::Blend }
+    }
+}
+
+impl Material for ChunkMaterial {
+    fn alpha_mode(&self) -> AlphaMode {
+        self.alpha_mode
+    }
+
+    fn vertex_shader() -> ShaderRef {
+        "shaders/chunk.wgsl".into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        "shaders/chunk.wgsl".into()
+    }
+
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayout,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        let mut attributes = vec![ATTRIBUTE_VOXEL.at_shader_location(0)];
+        if layout.contains(ATTRIBUTE_FLUID_DROP) {
+            attributes.push(ATTRIBUTE_FLUID_DROP.at_shader_location(1));
+            descriptor.vertex.shader_defs.push("FLUID_DROP".to_string());
+        }
+
+        let vertex_layout = layout.get_layout(&attributes)?;
+        descriptor.vertex.buffers = vec![vertex_layout];
+        Ok(())
+    }
+}
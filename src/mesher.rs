@@ -0,0 +1,555 @@
+use bevy::{prelude::*, render::{mesh::Indices, render_resource::PrimitiveTopology}};
+
+use crate::{
+    block::{Block, BlockRegistry, BlockRender, Face},
+    fluid::MAX_FLUID_LEVEL,
+    chunk::NeedsMesh,
+    light::{brightness, MAX_LIGHT},
+    manager::{ChunkManager, CHUNK_SIZE},
+    material::{ATTRIBUTE_FLUID_DROP, ATTRIBUTE_VOXEL},
+};
+
+/// Algorithm used to turn chunk data into meshes
+#[derive(Resource, Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Mesher {
+    /// One quad per visible block face
+    Naive,
+    /// Merges coplanar faces sharing a texture and shading into larger quads
+    #[default]
+    Greedy,
+}
+
+/// Vertex buffers of a chunk mesh, before being uploaded to a `Mesh`
+#[derive(Default)]
+pub struct MeshData {
+    /// Every vertex packed in a single value, see `PackedVertex`
+    pub vertices: Vec<u32>,
+    /// How far below its position each vertex is drawn, in sixteenths of a block, for the surface of fluids.
+    /// Only the mesh holding fluids has it, so that other meshes keep a single value per vertex.
+    pub drops: Option<Vec<u32>>,
+    pub indices: Vec<u32>,
+}
+
+/// Meshes of a chunk, one for each way blocks are rendered
+pub struct ChunkMeshes {
+    pub opaque: MeshData,
+    pub cutout: MeshData,
+    /// Fluids are translucent, so this is the only mesh with lowered vertices
+    pub translucent: MeshData,
+}
+
+impl Default for ChunkMeshes {
+    fn default() -> Self {
+        Self {
+            opaque: MeshData::default(),
+            cutout: MeshData::default(),
+            translucent: MeshData::with_drops(),
+        }
+    }
+}
+
+impl ChunkMeshes {
+    /// @returns The mesh the faces of blocks rendered this way go into, or None if they aren't drawn
+    pub fn layer_mut(&mut self, render: BlockRender) -> Option<&mut MeshData> {
+        match render {
+            BlockRender::Invisible => None,
+            BlockRender::Opaque => Some(&mut self.opaque),
+            BlockRender::Cutout => Some(&mut self.cutout),
+            BlockRender::Translucent => Some(&mut self.translucent),
+        }
+    }
+}
+
+/// Vertex of a chunk mesh packed in a u32, decoded by `shaders/chunk.wgsl`.
+/// From the lowest bits: the position inside of the chunk (5 bits per axis, from 0 to 16), the face (3 bits),
+/// the ambient occlusion (2 bits), the light level (4 bits) and the atlas tile (4 bits per axis).
+/// Normals and texture coordinates are rebuilt from the face and the position.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PackedVertex(pub u32);
+
+impl PackedVertex {
+    pub fn new(pos: IVec3, face: Face, shade: Shade, tile: IVec2) -> Self {
+        debug_assert!(pos.cmpge(IVec3::ZERO).all() && pos.cmple(IVec3::splat(CHUNK_SIZE as i32)).all());
+        debug_assert!(tile.cmpge(IVec2::ZERO).all() && tile.cmplt(IVec2::splat(16)).all());
+
+        let (x, y, z) = (pos.x as u32, pos.y as u32, pos.z as u32);
+        Self(
+            x | y << 5 | z << 10
+                | (face as u32) << 15
+                | (shade.ao as u32) << 18
+                | (shade.light as u32) << 20
+                | (tile.x as u32) << 24
+                | (tile.y as u32) << 28,
+        )
+    }
+
+    /// Decodes the vertex the way the shader does
+    /// @returns The position, face, shade and atlas tile the vertex was packed from
+    pub fn unpack(self) -> (IVec3, Face, Shade, IVec2) {
+        let bits = |shift: u32, width: u32| self.0 >> shift & ((1 << width) - 1);
+        (
+            IVec3::new(bits(0, 5) as i32, bits(5, 5) as i32, bits(10, 5) as i32),
+            Face::ALL[bits(15, 3) as usize],
+            Shade { ao: bits(18, 2) as u8, light: bits(20, 4) as u8 },
+            IVec2::new(bits(24, 4) as i32, bits(28, 4) as i32),
+        )
+    }
+}
+
+/// @returns The corners of a face of a unit block centered on the origin, in clockwise order
+pub const fn face_points(face: Face) -> [Vec3; 4] {
+    use Face::*;
+    match face {
+        TOP => [
+            Vec3::new(0.5, 0.5, -0.5),
+            Vec3::new(0.5, 0.5, 0.5),
+            Vec3::new(-0.5, 0.5, 0.5),
+            Vec3::new(-0.5, 0.5, -0.5),
+        ],
+        BOTTOM => [
+            Vec3::new(0.5, -0.5, 0.5),
+            Vec3::new(0.5, -0.5, -0.5),
+            Vec3::new(-0.5, -0.5, -0.5),
+            Vec3::new(-0.5, -0.5, 0.5),
+        ],
+        EAST => [
+            Vec3::new(0.5, 0.5, 0.5),
+            Vec3::new(0.5, 0.5, -0.5),
+            Vec3::new(0.5, -0.5, -0.5),
+            Vec3::new(0.5, -0.5, 0.5),
+        ],
+        WEST => [
+            Vec3::new(-0.5, -0.5, 0.5),
+            Vec3::new(-0.5, -0.5, -0.5),
+            Vec3::new(-0.5, 0.5, -0.5),
+            Vec3::new(-0.5, 0.5, 0.5),
+        ],
+        NORTH => [
+            Vec3::new(-0.5, 0.5, 0.5),
+            Vec3::new(0.5, 0.5, 0.5),
+            Vec3::new(0.5, -0.5, 0.5),
+            Vec3::new(-0.5, -0.5, 0.5),
+        ],
+        SOUTH => [
+            Vec3::new(-0.5, -0.5, -0.5),
+            Vec3::new(0.5, -0.5, -0.5),
+            Vec3::new(0.5, 0.5, -0.5),
+            Vec3::new(-0.5, 0.5, -0.5),
+        ],
+    }
+}
+
+/// Index of the only non zero component of the vector
+fn axis_of(v: Vec3) -> usize {
+    (0..3).find(|&i| v[i] != 0.0).unwrap()
+}
+
+/// @returns The axis along the normal of the face, and the axes the u and v texture coordinates follow
+pub fn face_axes(face: Face) -> (usize, usize, usize) {
+    let p = face_points(face);
+    (axis_of(face.normal_vec3()), axis_of(p[1] - p[0]), axis_of(p[2] - p[1]))
+}
+
+/// @returns The size in blocks of one cell of the given lod
+pub fn lod_cell(lod: u32) -> IVec3 {
+    IVec3::splat(2i32.pow(lod))
+}
+
+impl MeshData {
+    /// @returns An empty mesh whose vertices can be lowered, for fluids
+    pub fn with_drops() -> Self {
+        Self { drops: Some(Vec::new()), ..default() }
+    }
+
+    /// Adds a quad of the given face covering the box from `origin` to `origin + size`,
+    /// with `shade` the lighting of each corner. The texture repeats on every block the quad covers.
+    /// The top corners of the quad are lowered by `drop` sixteenths of a block, which only meshes made `with_drops` can do.
+    pub fn add_quad(&mut self, face: Face, origin: IVec3, size: IVec3, tile: IVec2, shade: [Shade; 4], drop: u8) {
+        debug_assert!(drop < 16);
+        debug_assert!(drop == 0 || self.drops.is_some(), "only meshes made with drops can lower their vertices");
+        let idx = self.vertices.len() as u32;
+
+        for (point, shade) in face_points(face).into_iter().zip(shade) {
+            let corner = origin + ((point + 0.5) * size.as_vec3()).as_ivec3();
+            self.vertices.push(PackedVertex::new(corner, face, shade, tile).0);
+            if let Some(drops) = &mut self.drops {
+                drops.push(if point.y > 0.0 { drop as u32 } else { 0 });
+            }
+        }
+
+        let ao = shade.map(|s| s.ao);
+        // Split the quad along the darkest diagonal, so that occlusion is interpolated the same way on every face
+        if ao[0] as u32 + ao[2] as u32 > ao[1] as u32 + ao[3] as u32 {
+            self.indices.extend_from_slice(&[idx + 3, idx + 2, idx + 1, idx + 1, idx, idx + 3]);
+        } else {
+            self.indices.extend_from_slice(&[idx + 2, idx + 1, idx, idx, idx + 3, idx + 2]);
+        }
+    }
+
+    pub fn into_mesh(self) -> Mesh {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        self.apply(&mut mesh);
+        mesh
+    }
+
+    pub fn apply(self, mesh: &mut Mesh) {
+        mesh.insert_attribute(ATTRIBUTE_VOXEL, self.vertices);
+        if let Some(drops) = self.drops {
+            mesh.insert_attribute(ATTRIBUTE_FLUID_DROP, drops);
+        }
+        mesh.set_indices(Some(Indices::U32(self.indices)));
+    }
+}
+
+/// Reorders the quads of a mesh from the farthest to the closest to `eye`, given relative to the mesh,
+/// so that blended faces are drawn over the ones behind them
+pub fn sort_quads(vertices: &[u32], indices: &mut [u32], eye: Vec3) {
+    // Quads are made of 4 consecutive vertices, and the 6 indices of a quad stay together
+    let distance = |quad: &[u32]| {
+        let first = *quad.iter().min().unwrap() as usize;
+        let center = vertices[first..first + 4]
+            .iter()
+            .map(|&v| PackedVertex(v).unpack().0.as_vec3())
+            .sum::<Vec3>()
+            / 4.0;
+        center.distance_squared(eye)
+    };
+
+    let mut quads = indices.chunks_exact(6).map(|quad| (distance(quad), [quad[0], quad[1], quad[2], quad[3], quad[4], quad[5]])).collect::<Vec<_>>();
+    quads.sort_unstable_by(|a, b| b.0.total_cmp(&a.0));
+
+    for (dst, (_, quad)) in indices.chunks_exact_mut(6).zip(quads) {
+        dst.copy_from_slice(&quad);
+    }
+}
+
+/// Lighting of a face corner, turned into a brightness by the chunk shader
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Shade {
+    /// Number of free neighbours, from 0 to 3
+    pub ao: u8,
+    /// Light level, averaged over the neighbours
+    pub light: u8,
+}
+
+/// @returns The mesh of a single block going from the origin to (1, 1, 1), for blocks drawn outside of chunks.
+/// Every face gets the given light level and no ambient occlusion.
+pub fn block_mesh(registry: &BlockRegistry, block: Block, light: u8) -> MeshData {
+    let info = registry.get(block);
+    let shade = Shade { ao: 3, light };
+
+    let mut data = MeshData::default();
+    for face in Face::ALL {
+        data.add_quad(face, IVec3::ZERO, IVec3::ONE, info.tile(face), [shade; 4], 0);
+    }
+    data
+}
+
+/// @returns Wether the block at the given position hides the faces touching it, considering non-generated chunks as empty
+fn occludes(manager: &ChunkManager, registry: &BlockRegistry, key: IVec3, local_pos: IVec3) -> bool {
+    manager
+        .get_with_adjacent(key, local_pos)
+        .is_some_and(|b| registry.get(b).occludes())
+}
+
+/// @returns How far below the top of its cell the surface of the fluid at the given position is, in sixteenths of a block.
+/// Fluid with more of itself above reaches the top, and blocks that aren't fluids have no drop.
+fn fluid_drop(manager: &ChunkManager, registry: &BlockRegistry, key: IVec3, local_pos: IVec3, lod: u32) -> u8 {
+    let Some(info) = manager.get_with_adjacent(key, local_pos).map(|b| registry.get(b)) else { return 0 };
+    let Some(fluid) = &info.fluid else { return 0 };
+
+    let above = manager.get_with_adjacent(key, local_pos + IVec3::Y * 2i32.pow(lod));
+    if above.is_some_and(|b| registry.get(b).same_fluid(info)) {
+        return 0;
+    }
+
+    let height = fluid.level as f32 / (MAX_FLUID_LEVEL + 1) as f32;
+    ((1.0 - height) * 16.0).round() as u8
+}
+
+/// Returns wether the face of the block at the given position isn't hidden by its neighbour.
+/// Faces between two blocks of the same type are hidden unless the block says otherwise.
+/// Between two levels of a fluid, only the sides above a lower surface are visible.
+/// Faces against a chunk meshed at another lod are always visible: the surfaces of both chunks don't line up,
+/// and the faces on each side of the border act as a skirt hiding the seam between them.
+pub fn face_visible(manager: &ChunkManager, registry: &BlockRegistry, key: IVec3, local_pos: IVec3, face: Face, lod: u32) -> bool {
+    let neighbour = local_pos + face.normal() * 2i32.pow(lod);
+
+    let (offset, _) = ChunkManager::get_keys(neighbour);
+    if offset != IVec3::ZERO && manager.meshes.get(&(key + offset)).is_some_and(|&(_, l)| l != lod) {
+        return true;
+    }
+
+    let Some(other) = manager.get_with_adjacent(key, neighbour) else { return true };
+    let block = manager.get_with_adjacent(key, local_pos).unwrap_or(Block::AIR);
+    if registry.get(block).same_fluid(registry.get(other)) {
+        return face.is_side() && fluid_drop(manager, registry, key, neighbour, lod) > fluid_drop(manager, registry, key, local_pos, lod);
+    }
+    if other == block {
+        return !registry.get(block).cull_same;
+    }
+    // A lowered fluid surface can be seen through the gap under the block above it
+    if face == Face::TOP && fluid_drop(manager, registry, key, local_pos, lod) > 0 {
+        return true;
+    }
+
+    !registry.get(other).occludes()
+}
+
+/// Computes the ambient occlusion and smooth lighting of each corner of a face,
+/// from the block in front of the face and the 2 sides and the corner touching it
+/// @returns The shading of each corner, in the same order as `face_points`
+pub fn vertex_shade(manager: &ChunkManager, registry: &BlockRegistry, key: IVec3, local_pos: IVec3, face: Face, lod: u32) -> [Shade; 4] {
+    let (_, a, b) = face_axes(face);
+    let cell = lod_cell(lod);
+    let front = local_pos + face.normal() * 2i32.pow(lod);
+
+    face_points(face).map(|point| {
+        let mut offset_a = IVec3::ZERO;
+        offset_a[a] = point[a].signum() as i32 * cell[a];
+        let mut offset_b = IVec3::ZERO;
+        offset_b[b] = point[b].signum() as i32 * cell[b];
+
+        let side_a = occludes(manager, registry, key, front + offset_a);
+        let side_b = occludes(manager, registry, key, front + offset_b);
+        // The corner can't be seen through two full sides
+        let corner = (side_a && side_b) || occludes(manager, registry, key, front + offset_a + offset_b);
+
+        let ao = if side_a && side_b { 0 } else { 3 - side_a as u8 - side_b as u8 - corner as u8 };
+
+        let samples = [(true, front), (!side_a, front + offset_a), (!side_b, front + offset_b), (!corner, front + offset_a + offset_b)];
+        let (sum, count) = samples
+            .into_iter()
+            .filter(|&(free, _)| free)
+            .filter_map(|(_, pos)| manager.light_with_adjacent(key, pos))
+            .fold((0u32, 0u32), |(sum, count), l| (sum + brightness(l) as u32, count + 1));
+        let light = (sum + count / 2).checked_div(count).map_or(MAX_LIGHT, |l| l as u8);
+
+        Shade { ao, light }
+    })
+}
+
+/// Meshes a chunk at the given lod.
+/// Above lod 0, `manager` is expected to be downsampled to that lod (see `ChunkManager::downsample`),
+/// so that sampling the first block of a cell gives the block representing it.
+pub fn build_mesh(mesher: Mesher, manager: &ChunkManager, registry: &BlockRegistry, key: IVec3, lod: u32) -> ChunkMeshes {
+    match mesher {
+        Mesher::Naive => naive_mesh(manager, registry, key, lod),
+        Mesher::Greedy => greedy_mesh(manager, registry, key, lod),
+    }
+}
+
+pub fn naive_mesh(manager: &ChunkManager, registry: &BlockRegistry, key: IVec3, lod: u32) -> ChunkMeshes {
+    let mut meshes = ChunkMeshes::default();
+    let Some(data) = manager.chunks.get(&key) else { return meshes };
+
+    let cell = lod_cell(lod);
+    for (block, x, y, z) in data.all_blocks_lod(lod) {
+        let info = registry.get(block);
+        let Some(mesh) = meshes.layer_mut(info.render) else { continue };
+
+        let local_pos = IVec3::new(x as i32, y as i32, z as i32);
+        for face in Face::ALL {
+            if face_visible(manager, registry, key, local_pos, face, lod) {
+                let shade = vertex_shade(manager, registry, key, local_pos, face, lod);
+                let drop = fluid_drop(manager, registry, key, local_pos, lod);
+                mesh.add_quad(face, local_pos, cell, info.tile(face), shade, drop);
+            }
+        }
+    }
+
+    meshes
+}
+
+pub fn greedy_mesh(manager: &ChunkManager, registry: &BlockRegistry, key: IVec3, lod: u32) -> ChunkMeshes {
+    let mut meshes = ChunkMeshes::default();
+    let Some(data) = manager.chunks.get(&key) else { return meshes };
+
+    let cell = lod_cell(lod);
+    let dims = IVec3::splat(CHUNK_SIZE as i32) / cell;
+
+    for face in Face::ALL {
+        let (n, a, b) = face_axes(face);
+        let (width, height) = (dims[a], dims[b]);
+        let idx = |i: i32, j: i32| (i + j * width) as usize;
+
+        // Render layer, texture, corner shading and fluid drop of every visible face in the current slice
+        let mut mask = vec![None; (width * height) as usize];
+
+        for d in 0..dims[n] {
+            for (j, i) in (0..height).flat_map(|j| (0..width).map(move |i| (j, i))) {
+                let mut c = IVec3::ZERO;
+                c[n] = d;
+                c[a] = i;
+                c[b] = j;
+                let local_pos = c * cell;
+
+                let info = registry.get(data.get_unchecked(local_pos));
+                mask[idx(i, j)] = (info.render != BlockRender::Invisible && face_visible(manager, registry, key, local_pos, face, lod))
+                    .then(|| {
+                        let shade = vertex_shade(manager, registry, key, local_pos, face, lod);
+                        (info.render, info.tile(face), shade, fluid_drop(manager, registry, key, local_pos, lod))
+                    });
+            }
+
+            for j in 0..height {
+                let mut i = 0;
+                while i < width {
+                    let Some(current @ (render, tile, shade, drop)) = mask[idx(i, j)] else { i += 1; continue };
+
+                    // Extend the quad along u, then along v as long as every face of the row matches
+                    let mut w = 1;
+                    while i + w < width && mask[idx(i + w, j)] == Some(current) {
+                        w += 1;
+                    }
+                    let mut h = 1;
+                    'grow: while j + h < height {
+                        for k in 0..w {
+                            if mask[idx(i + k, j + h)] != Some(current) {
+                                break 'grow;
+                            }
+                        }
+                        h += 1;
+                    }
+
+                    for (y, x) in (j..j + h).flat_map(|y| (i..i + w).map(move |x| (y, x))) {
+                        mask[idx(x, y)] = None;
+                    }
+
+                    let mut origin = IVec3::ZERO;
+                    origin[n] = d;
+                    origin[a] = i;
+                    origin[b] = j;
+                    let mut size = IVec3::ONE;
+                    size[a] = w;
+                    size[b] = h;
+
+                    if let Some(mesh) = meshes.layer_mut(render) {
+                        mesh.add_quad(face, origin * cell, size * cell, tile, shade, drop);
+                    }
+                    i += w;
+                }
+            }
+        }
+    }
+
+    meshes
+}
+
+/// Switches between the naive and greedy mesher, and regenerates every chunk mesh
+pub fn toggle_mesher(
+    mut commands: Commands,
+    mut mesher: ResMut<Mesher>,
+    manager: Res<ChunkManager>,
+    keyboard: Res<Input<KeyCode>>,
+) {
+    if !keyboard.just_pressed(KeyCode::G) {
+        return;
+    }
+
+    *mesher = match *mesher {
+        Mesher::Naive => Mesher::Greedy,
+        Mesher::Greedy => Mesher::Naive,
+    };
+    info!("Using the {:?} mesher", *mesher);
+
+    for &(entity, lod) in manager.meshes.values() {
+        commands.entity(entity).insert(NeedsMesh(lod));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::utils::HashSet;
+
+    use super::*;
+    use crate::{terrain::generate_chunk, test_util::generator};
+
+    /// @returns The registry and a chunk crossing the surface of the terrain, generated along with its neighbours
+    fn generated_terrain() -> (BlockRegistry, ChunkManager, IVec3) {
+        let registry = BlockRegistry::load("assets/blocks.ron").unwrap();
+        let (noise, caves, biomes, blocks) = generator(7);
+
+        let generate = |key| generate_chunk(key, &noise, &caves, &biomes, &blocks);
+        let key = (0..16)
+            .map(|y| IVec3::new(0, y, 0))
+            .find(|&key| generate(key).single_block().is_none())
+            .expect("no chunk crosses the surface");
+
+        let mut manager = ChunkManager::default();
+        for k in ChunkManager::adjacent_keys(key).chain([key]) {
+            manager.chunks.insert(k, generate(k));
+        }
+        (registry, manager, key)
+    }
+
+    /// @returns Every block face covered by the quads of the mesh, with the position of the block it belongs to
+    fn covered_faces(mesh: &MeshData) -> HashSet<(IVec3, Face)> {
+        let mut faces = HashSet::new();
+        for quad in mesh.vertices.chunks_exact(4) {
+            let vertices = quad.iter().map(|&v| PackedVertex(v).unpack()).collect::<Vec<_>>();
+            let face = vertices[0].1;
+            let min = vertices.iter().map(|v| v.0).reduce(IVec3::min).unwrap();
+            let max = vertices.iter().map(|v| v.0).reduce(IVec3::max).unwrap();
+
+            // Quads lie on the side of their blocks the face points to
+            let (n, a, b) = face_axes(face);
+            let mut block = min;
+            block[n] -= face.normal()[n].max(0);
+            for (i, j) in (min[a]..max[a]).flat_map(|i| (min[b]..max[b]).map(move |j| (i, j))) {
+                let mut pos = block;
+                pos[a] = i;
+                pos[b] = j;
+                assert!(faces.insert((pos, face)), "face {face:?} of {pos} is covered twice");
+            }
+        }
+        faces
+    }
+
+    #[test]
+    fn packed_vertex_round_trip() {
+        let size = CHUNK_SIZE as i32;
+        let positions = [IVec3::ZERO, IVec3::new(size, 0, 0), IVec3::new(0, size, 0), IVec3::new(0, 0, size), IVec3::splat(size)];
+
+        for pos in positions {
+            for face in Face::ALL {
+                for ao in 0..=3 {
+                    for light in 0..=MAX_LIGHT {
+                        for tile in [IVec2::ZERO, IVec2::new(15, 0), IVec2::new(0, 15), IVec2::new(15, 15)] {
+                            let shade = Shade { ao, light };
+                            assert_eq!(PackedVertex::new(pos, face, shade, tile).unpack(), (pos, face, shade, tile));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fluid_drop_only_lowers_the_top_corners() {
+        let mut mesh = MeshData::with_drops();
+        let shade = Shade { ao: 3, light: MAX_LIGHT };
+        mesh.add_quad(Face::NORTH, IVec3::ZERO, IVec3::ONE, IVec2::ZERO, [shade; 4], 15);
+
+        for (&vertex, &drop) in mesh.vertices.iter().zip(mesh.drops.as_ref().unwrap()) {
+            let expected = if PackedVertex(vertex).unpack().0.y > 0 { 15 } else { 0 };
+            assert_eq!(drop, expected);
+        }
+    }
+
+    #[test]
+    fn greedy_mesh_covers_the_same_faces_with_fewer_triangles() {
+        let (registry, manager, key) = generated_terrain();
+
+        let naive = naive_mesh(&manager, &registry, key, 0);
+        let greedy = greedy_mesh(&manager, &registry, key, 0);
+
+        assert!(!naive.opaque.indices.is_empty());
+        assert!(greedy.opaque.indices.len() < naive.opaque.indices.len());
+
+        for (naive, greedy) in [(&naive.opaque, &greedy.opaque), (&naive.cutout, &greedy.cutout), (&naive.translucent, &greedy.translucent)] {
+            assert!(greedy.indices.len() <= naive.indices.len());
+            assert_eq!(covered_faces(greedy), covered_faces(naive));
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use std::sync::Arc;
+
+use noise::{Add, Constant, Fbm, MultiFractal, Multiply, NoiseFn, OpenSimplex, RidgedMulti, ScaleBias, Seedable, Turbulence};
+use serde::{Deserialize, Serialize};
+
+/// Noise function built from a `NoiseNode`, shared between the threads generating terrain
+pub type NoiseGraph = Arc<dyn NoiseFn<f64, 3> + Send + Sync>;
+
+type BoxedNoise = Box<dyn NoiseFn<f64, 3> + Send + Sync>;
+
+/// Octaves of a fractal noise
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Fractal {
+    /// Number of layers of noise added together
+    pub octaves: usize,
+    /// Frequency of the first octave, in cycles per block
+    pub frequency: f64,
+    /// Factor applied to the amplitude of each successive octave
+    pub persistence: f64,
+    /// Factor applied to the frequency of each successive octave
+    pub lacunarity: f64,
+}
+
+impl Default for Fractal {
+    fn default() -> Self {
+        Self {
+            octaves: 1,
+            frequency: 1.0 / 32.0,
+            persistence: 0.5,
+            lacunarity: 2.0,
+        }
+    }
+}
+
+/// Node of a noise graph, as written in the world generation config.
+/// Nodes output values roughly between -1 and 1.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum NoiseNode {
+    Constant(f64),
+    /// Fractal brownian motion, octaves of simplex noise added together
+    Fbm(Fractal),
+    /// Octaves of folded simplex noise, giving sharp ridges
+    Ridged(Fractal),
+    Add(Vec<NoiseNode>),
+    Multiply(Vec<NoiseNode>),
+    /// Multiplies the source by `scale` then adds `bias`
+    ScaleBias { source: Box<NoiseNode>, scale: f64, bias: f64 },
+    /// Domain warping, offsets the position the source is sampled at by up to `power` blocks
+    Warp { source: Box<NoiseNode>, frequency: f64, power: f64, roughness: usize },
+    /// Remaps the source through a piecewise linear curve going through each (input, output) point
+    Spline { source: Box<NoiseNode>, points: Vec<(f64, f64)> },
+}
+
+impl Default for NoiseNode {
+    fn default() -> Self {
+        NoiseNode::Fbm(Fractal::default())
+    }
+}
+
+/// Curve going through control points sorted by input, flat past its ends
+struct Spline {
+    source: BoxedNoise,
+    points: Vec<(f64, f64)>,
+}
+
+impl NoiseFn<f64, 3> for Spline {
+    fn get(&self, point: [f64; 3]) -> f64 {
+        let x = self.source.get(point);
+
+        let i = self.points.partition_point(|&(input, _)| input < x);
+        if i == 0 {
+            return self.points[0].1;
+        }
+        let Some(&(x1, y1)) = self.points.get(i) else { return self.points[i - 1].1 };
+        let (x0, y0) = self.points[i - 1];
+
+        y0 + (y1 - y0) * (x - x0) / (x1 - x0)
+    }
+}
+
+impl NoiseNode {
+    /// Creates the noise function described by this node.
+    /// Every generator receives its own seed, counting up from `seed` in the order the graph is written.
+    pub fn build(&self, mut seed: u32) -> NoiseGraph {
+        Arc::from(self.build_node(&mut seed))
+    }
+
+    fn build_node(&self, seed: &mut u32) -> BoxedNoise {
+        let mut next_seed = || {
+            let current = *seed;
+            *seed = seed.wrapping_add(1);
+            current
+        };
+
+        match self {
+            NoiseNode::Constant(value) => Box::new(Constant::new(*value)),
+            NoiseNode::Fbm(f) => Box::new(
+                Fbm::<OpenSimplex>::new(next_seed())
+                    .set_octaves(f.octaves.max(1))
+                    .set_frequency(f.frequency)
+                    .set_persistence(f.persistence)
+                    .set_lacunarity(f.lacunarity),
+            ),
+            NoiseNode::Ridged(f) => Box::new(
+                RidgedMulti::<OpenSimplex>::new(next_seed())
+                    .set_octaves(f.octaves.max(1))
+                    .set_frequency(f.frequency)
+                    .set_persistence(f.persistence)
+                    .set_lacunarity(f.lacunarity),
+            ),
+            NoiseNode::Add(nodes) => nodes
+                .iter()
+                .map(|n| n.build_node(seed))
+                .reduce(|a, b| Box::new(Add::new(a, b)))
+                .unwrap_or_else(|| Box::new(Constant::new(0.0))),
+            NoiseNode::Multiply(nodes) => nodes
+                .iter()
+                .map(|n| n.build_node(seed))
+                .reduce(|a, b| Box::new(Multiply::new(a, b)))
+                .unwrap_or_else(|| Box::new(Constant::new(1.0))),
+            NoiseNode::ScaleBias { source, scale, bias } => {
+                Box::new(ScaleBias::new(source.build_node(seed)).set_scale(*scale).set_bias(*bias))
+            }
+            NoiseNode::Warp { source, frequency, power, roughness } => {
+                let warp_seed = next_seed();
+                Box::new(
+                    Turbulence::<_, Fbm<OpenSimplex>>::new(source.build_node(seed))
+                        .set_seed(warp_seed)
+                        .set_frequency(*frequency)
+                        .set_power(*power)
+                        .set_roughness((*roughness).max(1)),
+                )
+            }
+            NoiseNode::Spline { source, points } => {
+                let mut points = points.clone();
+                points.sort_by(|a, b| a.0.total_cmp(&b.0));
+                if points.is_empty() {
+                    points.push((0.0, 0.0));
+                }
+                Box::new(Spline { source: source.build_node(seed), points })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(source: &str, seed: u32) -> NoiseGraph {
+        ron::from_str::<NoiseNode>(source).unwrap().build(seed)
+    }
+
+    fn points() -> impl Iterator<Item = [f64; 3]> {
+        (0..64).map(|i| [i as f64 * 7.3, -i as f64 * 3.1, i as f64 * 11.9])
+    }
+
+    #[test]
+    fn fbm_is_seeded() {
+        let source = "Fbm((octaves: 4, frequency: 0.05, persistence: 0.5, lacunarity: 2.0))";
+        let (a, b, other) = (build(source, 3), build(source, 3), build(source, 4));
+
+        assert!(points().all(|p| a.get(p) == b.get(p)), "the same seed gives different noise");
+        assert!(points().any(|p| a.get(p) != other.get(p)), "different seeds give the same noise");
+        assert!(points().all(|p| (-1.0..=1.0).contains(&a.get(p))));
+    }
+
+    #[test]
+    fn scale_bias_and_splines_remap_their_source() {
+        let scaled = build("ScaleBias(source: Constant(0.5), scale: 3.0, bias: -1.0)", 0);
+        assert_eq!(scaled.get([0.0; 3]), 0.5);
+
+        // Points are sorted, and the curve is flat past its ends
+        let spline = |x: f64| {
+            build(&format!("Spline(source: Constant({x:?}), points: [(1.0, 0.0), (-1.0, -0.5), (0.0, 1.0)])"), 0).get([0.0; 3])
+        };
+        assert_eq!([-2.0, -1.0, -0.5, 0.0, 0.25, 1.0, 3.0].map(spline), [-0.5, -0.5, 0.25, 1.0, 0.75, 0.0, 0.0]);
+        assert_eq!(build("Spline(source: Constant(0.3), points: [])", 0).get([0.0; 3]), 0.0);
+    }
+
+    #[test]
+    fn warp_offsets_its_source() {
+        let source = "Fbm((octaves: 1, frequency: 0.05, persistence: 0.5, lacunarity: 2.0))";
+        // The warp takes the first seed, its source the next one
+        let plain = build(source, 9);
+        let warped = build(&format!("Warp(source: {source}, frequency: 0.02, power: 20.0, roughness: 2)"), 8);
+
+        assert!(points().any(|p| plain.get(p) != warped.get(p)), "the source isn't warped");
+        let still = build(&format!("Warp(source: {source}, frequency: 0.02, power: 0.0, roughness: 2)"), 8);
+        assert!(points().all(|p| plain.get(p) == still.get(p)));
+    }
+
+    #[test]
+    fn unknown_nodes_are_rejected() {
+        assert!(ron::from_str::<NoiseNode>("Octaves((octaves: 1))").is_err());
+        assert!(ron::from_str::<NoiseNode>("ScaleBias(source: Constant(1.0), scale: 2.0)").is_err());
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::VecDeque;
+
+use bevy::{prelude::*, render::primitives::Frustum, utils::HashSet};
+
+use crate::{
+    block::{BlockRegistry, Face},
+    chunk::chunk_in_frustum,
+    manager::{ChunkData, ChunkManager, CHUNK_SIZE, CHUNK_VOLUME},
+};
+
+/// Which faces of a chunk can be seen from which other faces, through the blocks that let light through
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Connectivity(u64);
+
+impl Connectivity {
+    pub const NONE: Connectivity = Connectivity(0);
+    pub const ALL: Connectivity = Connectivity((1 << 36) - 1);
+
+    #[inline]
+    fn bit(a: Face, b: Face) -> u64 {
+        1 << (a as u64 * 6 + b as u64)
+    }
+
+    pub fn connect(&mut self, a: Face, b: Face) {
+        self.0 |= Self::bit(a, b) | Self::bit(b, a);
+    }
+
+    /// @returns Wether something entering the chunk through face `a` can leave it through face `b`
+    pub fn connects(self, a: Face, b: Face) -> bool {
+        self.0 & Self::bit(a, b) != 0
+    }
+}
+
+/// @returns The faces of the chunk touched by the block at the given position
+fn border_faces(pos: IVec3) -> impl Iterator<Item = Face> {
+    let last = CHUNK_SIZE as i32 - 1;
+    Face::ALL.into_iter().filter(move |face| {
+        let n = face.normal();
+        let axis = (0..3).find(|&i| n[i] != 0).unwrap();
+        pos[axis] == if n[axis] > 0 { last } else { 0 }
+    })
+}
+
+/// Flood fills the blocks that let light through, connecting every pair of faces touched by the same region
+pub fn chunk_connectivity(data: &ChunkData, registry: &BlockRegistry) -> Connectivity {
+    if let Some(block) = data.single_block() {
+        return if registry.get(block).transparent() { Connectivity::ALL } else { Connectivity::NONE };
+    }
+
+    let size = CHUNK_SIZE as i32;
+    let index = |p: IVec3| (p.x + p.y * size + p.z * size * size) as usize;
+
+    let mut visited = vec![false; CHUNK_VOLUME];
+    let mut connectivity = Connectivity::NONE;
+    let mut queue = Vec::new();
+
+    for (x, y, z) in ChunkData::all() {
+        let start = IVec3::new(x as i32, y as i32, z as i32);
+        // Regions that don't touch the border can't connect anything, so the fill only starts from it
+        if border_faces(start).next().is_none() || visited[index(start)] || !registry.get(data.get_unchecked(start)).transparent() {
+            continue;
+        }
+
+        let mut faces = HashSet::new();
+        visited[index(start)] = true;
+        queue.push(start);
+        while let Some(pos) = queue.pop() {
+            faces.extend(border_faces(pos));
+
+            for face in Face::ALL {
+                let next = pos + face.normal();
+                let Some(block) = data.get(next) else { continue };
+                if !visited[index(next)] && registry.get(block).transparent() {
+                    visited[index(next)] = true;
+                    queue.push(next);
+                }
+            }
+        }
+
+        for &a in &faces {
+            for &b in &faces {
+                connectivity.connect(a, b);
+            }
+        }
+    }
+
+    connectivity
+}
+
+/// Finds the chunks that can be seen from the camera, walking the connectivity graph of the chunks from the one it is in.
+/// The walk only moves away from the camera and stays inside of the frustum.
+/// Chunks whose connectivity isn't known yet are considered see-through.
+pub fn visible_chunks(manager: &ChunkManager, camera_key: IVec3, frustum: &Frustum) -> HashSet<IVec3> {
+    let mut visible = HashSet::new();
+    // Each chunk is queued with the face it was entered through, and the directions taken to reach it
+    let mut queue = VecDeque::from([(camera_key, None::<Face>, 0u8)]);
+    visible.insert(camera_key);
+
+    while let Some((key, entered, directions)) = queue.pop_front() {
+        let connectivity = manager.connectivity.get(&key).copied().unwrap_or(Connectivity::ALL);
+
+        for face in Face::ALL {
+            let opposite = Face::from_normal(-face.normal()).unwrap();
+            // Going back in a direction already taken can only reach chunks hidden behind the ones already visited
+            if directions & (1 << opposite as u8) != 0 {
+                continue;
+            }
+            if entered.is_some_and(|entered| !connectivity.connects(entered, face)) {
+                continue;
+            }
+
+            let next = key + face.normal();
+            if visible.contains(&next) || !manager.entity_created(next) || !chunk_in_frustum(frustum, next) {
+                continue;
+            }
+
+            visible.insert(next);
+            queue.push_back((next, Some(opposite), directions | (1 << face as u8)));
+        }
+    }
+
+    visible
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::render::primitives::Plane;
+    use itertools::Itertools;
+
+    use super::*;
+    use crate::{block::Block, storage::PaletteStorage};
+
+    fn registry() -> BlockRegistry {
+        BlockRegistry::load("assets/blocks.ron").unwrap()
+    }
+
+    /// @returns The pairs of different faces of a chunk
+    fn face_pairs() -> impl Iterator<Item = (Face, Face)> {
+        Face::ALL.into_iter().cartesian_product(Face::ALL).filter(|(a, b)| a != b)
+    }
+
+    /// @returns A manager with an entity for each chunk in the given range on every axis, and the given connectivities
+    fn chunks(range: std::ops::RangeInclusive<i32>, connectivity: impl IntoIterator<Item = (IVec3, Connectivity)>) -> ChunkManager {
+        let mut manager = ChunkManager::default();
+        for ((x, y), z) in range.clone().cartesian_product(range.clone()).cartesian_product(range) {
+            manager.meshes.insert(IVec3::new(x, y, z), (Entity::from_raw(0), 0));
+        }
+        manager.connectivity.extend(connectivity);
+        manager
+    }
+
+    /// @returns A frustum containing every chunk close to the origin
+    fn everything() -> Frustum {
+        Frustum { planes: Face::ALL.map(|f| Plane::new(f.normal().as_vec3().extend(1e6))) }
+    }
+
+    #[test]
+    fn air_connects_every_face() {
+        let registry = registry();
+        let mut data = ChunkData::from_storage(PaletteStorage::Single(Block::AIR));
+        // A block in the middle doesn't stop anything from going around it
+        data.set_unchecked(IVec3::splat(8), registry.by_name("stone").unwrap());
+
+        let connectivity = chunk_connectivity(&data, &registry);
+        assert!(face_pairs().all(|(a, b)| connectivity.connects(a, b)));
+    }
+
+    #[test]
+    fn slabs_separate_their_sides() {
+        let registry = registry();
+        let mut data = ChunkData::from_storage(PaletteStorage::Single(Block::AIR));
+        for (x, z) in ChunkData::slice() {
+            data.set_unchecked(IVec3::new(x as i32, 8, z as i32), registry.by_name("stone").unwrap());
+        }
+
+        let connectivity = chunk_connectivity(&data, &registry);
+        for (a, b) in face_pairs() {
+            let separated = matches!((a, b), (Face::TOP, Face::BOTTOM) | (Face::BOTTOM, Face::TOP));
+            assert_eq!(connectivity.connects(a, b), !separated, "{a:?} to {b:?}");
+        }
+    }
+
+    #[test]
+    fn walk_doesnt_turn_back() {
+        // The chunk above the camera is opaque, so the one above it can only be reached by going back west or south
+        let above = IVec3::new(0, 2, 0);
+        let manager = chunks(-1..=2, [(IVec3::Y, Connectivity::NONE)]);
+
+        let visible = visible_chunks(&manager, IVec3::ZERO, &everything());
+        assert!(visible.contains(&IVec3::new(1, 2, 0)) && visible.contains(&IVec3::Y));
+        assert!(!visible.contains(&above), "the walk went back towards the camera to reach {above}");
+    }
+
+    #[test]
+    fn chunks_behind_walls_are_culled() {
+        let wall = (-1..=2).cartesian_product(-1..=2).map(|(y, z)| (IVec3::new(1, y, z), Connectivity::NONE));
+        let manager = chunks(-1..=2, wall);
+
+        let visible = visible_chunks(&manager, IVec3::ZERO, &everything());
+        // The wall itself is visible, but nothing behind it
+        assert!(visible.contains(&IVec3::X));
+        assert!(!visible.iter().any(|key| key.x == 2), "chunks behind the wall are visible");
+        assert!(visible.contains(&IVec3::new(-1, 2, -1)));
+    }
+}
@@ -0,0 +1,216 @@
+use std::{fmt, path::Path};
+
+use bevy::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Deserialize;
+
+use crate::{
+    block::{Block, BlockRegistry, Face, RegistryError},
+    manager::{ChunkData, CHUNK_SIZE},
+    terrain::chunk_rng,
+    Noise,
+};
+
+/// Entry of the ore distribution file, referring to blocks by name
+#[derive(Deserialize, Clone, Debug)]
+struct OreDefinition {
+    block: String,
+    host: String,
+    min_height: i32,
+    max_height: i32,
+    vein_size: u32,
+    attempts: u32,
+}
+
+/// How an ore is spread through the terrain
+#[derive(Clone, Debug)]
+pub struct OreInfo {
+    pub block: Block,
+    /// Only this block is replaced by the ore
+    pub host: Block,
+    /// Lowest height a vein can start at
+    pub min_height: i32,
+    /// Highest height a vein can start at
+    pub max_height: i32,
+    /// Maximum number of blocks in a vein
+    pub vein_size: u32,
+    /// Number of veins tried in every chunk
+    pub attempts: u32,
+}
+
+#[derive(Debug)]
+pub enum OreError {
+    Io(std::io::Error),
+    Parse(ron::error::SpannedError),
+    Registry(RegistryError),
+    /// The height range of an ore is empty
+    InvalidRange { block: String, min_height: i32, max_height: i32 },
+}
+
+impl fmt::Display for OreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use OreError::*;
+        match self {
+            Io(e) => write!(f, "could not read ore distribution: {e}"),
+            Parse(e) => write!(f, "could not parse ore distribution: {e}"),
+            Registry(e) => write!(f, "invalid ore distribution: {e}"),
+            InvalidRange { block, min_height, max_height } => {
+                write!(f, "ore `{block}` has an empty height range ({min_height} to {max_height})")
+            }
+        }
+    }
+}
+
+impl std::error::Error for OreError {}
+
+/// Distribution table of the ores, applied to every chunk after its terrain is generated
+#[derive(Resource, Clone)]
+pub struct OreTable {
+    pub ores: Vec<OreInfo>,
+}
+
+impl OreTable {
+    /// Reads the ore distribution from a RON file, resolving block names with the registry
+    pub fn load(path: impl AsRef<Path>, registry: &BlockRegistry) -> Result<Self, OreError> {
+        let source = std::fs::read_to_string(path).map_err(OreError::Io)?;
+        let definitions: Vec<OreDefinition> = ron::from_str(&source).map_err(OreError::Parse)?;
+
+        let ores = definitions
+            .into_iter()
+            .map(|d| {
+                if d.min_height > d.max_height {
+                    return Err(OreError::InvalidRange { block: d.block, min_height: d.min_height, max_height: d.max_height });
+                }
+
+                Ok(OreInfo {
+                    block: registry.by_name(&d.block).map_err(OreError::Registry)?,
+                    host: registry.by_name(&d.host).map_err(OreError::Registry)?,
+                    min_height: d.min_height,
+                    max_height: d.max_height,
+                    vein_size: d.vein_size,
+                    attempts: d.attempts,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { ores })
+    }
+}
+
+/// Salt of the random generator of ore veins, see `chunk_rng`
+const ORE_SALT: u64 = 1;
+
+/// Places ore veins in a generated chunk.
+/// Veins are random walks from a random starting block, and stay inside of the chunk.
+pub fn place_ores(key: IVec3, noise: &Noise, ores: &OreTable, data: &mut ChunkData) {
+    let mut rng = chunk_rng(noise, key, ORE_SALT);
+    let origin = key * CHUNK_SIZE as i32;
+    let bounds = 0..CHUNK_SIZE as i32;
+
+    for ore in &ores.ores {
+        for _ in 0..ore.attempts {
+            // Every number is drawn before skipping the attempt, so that each vein doesn't depend on the ones before it
+            let mut pos = IVec3::new(
+                rng.gen_range(bounds.clone()),
+                rng.gen_range(bounds.clone()),
+                rng.gen_range(bounds.clone()),
+            );
+            let size = rng.gen_range(1..=ore.vein_size.max(1));
+            let mut vein_rng = StdRng::seed_from_u64(rng.gen());
+
+            if !(ore.min_height..=ore.max_height).contains(&(origin.y + pos.y)) {
+                continue;
+            }
+
+            for _ in 0..size {
+                if data.get_unchecked(pos) == ore.host {
+                    data.set_unchecked(pos, ore.block);
+                }
+
+                let next = pos + Face::ALL[vein_rng.gen_range(0..Face::ALL.len())].normal();
+                if next.cmpge(IVec3::ZERO).all() && next.cmplt(IVec3::splat(CHUNK_SIZE as i32)).all() {
+                    pos = next;
+                }
+            }
+        }
+    }
+
+    data.compact();
+}
+
+#[cfg(test)]
+mod tests {
+    use noise::OpenSimplex;
+
+    use super::*;
+    use crate::storage::PaletteStorage;
+
+    fn registry() -> BlockRegistry {
+        BlockRegistry::load("assets/blocks.ron").unwrap()
+    }
+
+    /// @returns A single ore placed often, in large veins
+    fn table(registry: &BlockRegistry, min_height: i32, max_height: i32) -> OreTable {
+        let [block, host] = ["coal_ore", "stone"].map(|name| registry.by_name(name).unwrap());
+        OreTable { ores: vec![OreInfo { block, host, min_height, max_height, vein_size: 12, attempts: 20 }] }
+    }
+
+    /// @returns The blocks of a chunk of stone once the ores are placed in it
+    fn place(key: IVec3, seed: u32, ores: &OreTable) -> Vec<Block> {
+        let mut data = ChunkData::from_storage(PaletteStorage::Single(ores.ores[0].host));
+        place_ores(key, &Noise(OpenSimplex::new(seed)), ores, &mut data);
+        data.all_blocks().map(|(b, ..)| b).collect()
+    }
+
+    #[test]
+    fn veins_only_depend_on_the_chunk_and_seed() {
+        let registry = registry();
+        let ores = table(&registry, -100, 100);
+        let key = IVec3::new(3, 0, -2);
+
+        let blocks = place(key, 5, &ores);
+        assert!(blocks.contains(&ores.ores[0].block), "no vein was placed");
+        assert_eq!(blocks, place(key, 5, &ores));
+        assert_ne!(blocks, place(key + IVec3::X, 5, &ores), "neighbouring chunks have the same veins");
+        assert_ne!(blocks, place(key, 6, &ores), "different seeds give the same veins");
+    }
+
+    #[test]
+    fn ores_only_replace_their_host() {
+        let registry = registry();
+        let ores = table(&registry, -100, 100);
+        let dirt = registry.by_name("dirt").unwrap();
+
+        // Layers of stone and dirt
+        let mut data = ChunkData::from_storage(PaletteStorage::Single(ores.ores[0].host));
+        for (x, y, z) in ChunkData::all().filter(|&(_, y, _)| y % 2 == 0) {
+            data.set_unchecked(IVec3::new(x as i32, y as i32, z as i32), dirt);
+        }
+        place_ores(IVec3::ZERO, &Noise(OpenSimplex::new(5)), &ores, &mut data);
+
+        let mut placed = 0;
+        for (block, _, y, _) in data.all_blocks() {
+            if y % 2 == 0 {
+                assert_eq!(block, dirt, "ore replaced dirt at height {y}");
+            } else if block == ores.ores[0].block {
+                placed += 1;
+            }
+        }
+        assert!(placed > 0, "no vein was placed");
+    }
+
+    #[test]
+    fn veins_start_inside_of_their_height_range() {
+        let registry = registry();
+        let size = CHUNK_SIZE as i32;
+        let ores = table(&registry, size + 4, size + 8);
+        let ore = ores.ores[0].block;
+
+        for seed in 0..4 {
+            assert!(place(IVec3::Y, seed, &ores).contains(&ore), "no vein was placed with seed {seed}");
+            for key in [IVec3::ZERO, IVec3::Y * 2, IVec3::NEG_Y] {
+                assert!(!place(key, seed, &ores).contains(&ore), "vein placed in {key} with seed {seed}");
+            }
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use bevy::{prelude::*, time::FixedTimesteps};
+
+use crate::{
+    block::BlockRegistry,
+    manager::ChunkManager,
+    player::{BoundingBox, Velocity, VelocityMask},
+};
+
+/// Number of physics steps per second
+pub const STEPS_PER_SECOND: f64 = 60.0;
+
+/// Duration of a physics step in seconds
+pub const STEP: f32 = (1.0 / STEPS_PER_SECOND) as f32;
+
+/// Label of the fixed timestep running the physics, to know how far into the next step the frame is
+pub const PHYSICS_TIMESTEP: &str = "physics";
+
+/// Acceleration pulling bodies down, in blocks per second squared
+pub const GRAVITY: f32 = 30.0;
+pub const TERMINAL_VELOCITY: f32 = 10.0;
+
+/// Entity moved by `step_bodies`, falling and colliding with the full blocks of the world.
+/// Its transform is the center of its bounding box.
+#[derive(Component, Clone, Copy)]
+pub struct VoxelBody {
+    /// Acceleration pulling the body down, in blocks per second squared
+    pub gravity: f32,
+    /// Wether a block below the body stopped it during the last step
+    pub on_ground: bool,
+    /// Position of the body before the last step, for followers to move smoothly between steps
+    pub previous: Vec3,
+}
+
+impl Default for VoxelBody {
+    fn default() -> Self {
+        Self { gravity: GRAVITY, on_ground: false, previous: Vec3::ZERO }
+    }
+}
+
+#[derive(Bundle)]
+pub struct VoxelBodyBundle {
+    pub body: VoxelBody,
+    pub bounding: BoundingBox,
+    pub velocity: Velocity,
+    pub mask: VelocityMask,
+    #[bundle]
+    pub spatial: SpatialBundle,
+}
+
+impl VoxelBodyBundle {
+    /// @returns A body at rest, with a bounding box of the given size centered on the given position
+    pub fn new(center: Vec3, size: Vec3) -> Self {
+        Self {
+            body: VoxelBody { previous: center, ..default() },
+            bounding: BoundingBox { center, half_extents: size / 2.0 },
+            velocity: Velocity(Vec3::ZERO),
+            mask: VelocityMask(Vec3::ONE),
+            spatial: SpatialBundle::from_transform(Transform::from_translation(center)),
+        }
+    }
+}
+
+/// Keeps an entity at a fixed offset from a body, like the camera following the player
+#[derive(Component)]
+pub struct FollowBody {
+    pub body: Entity,
+    pub offset: Vec3,
+}
+
+/// Stops the velocity along the axes on which the bounding box would move into a full block during the next `delta` seconds,
+/// and masks these axes so that the entity doesn't move along them
+pub fn collide(bounding: &BoundingBox, velocity: &mut Velocity, mask: &mut VelocityMask, delta: f32, manager: &ChunkManager, registry: &BlockRegistry) {
+    mask.0 = Vec3::ONE;
+
+    let mut check_axis = |dir: Vec3| {
+        if !bounding.points().into_iter().any(|point| {
+            let ( player_key, player_pos ) = ChunkManager::get_keys((point + velocity.0*dir*delta).floor().as_ivec3());
+
+            // Chunks that aren't generated yet are solid, like the ones that aren't loaded
+            if let Some(c) = manager.chunks.get(&player_key).filter(|c| c.generated) {
+                registry.get(c.get_unchecked(player_pos)).full()
+            }
+            else {
+                true
+            }
+        }){ return };
+
+        // let target = player_pos - (velocity.0*dir).signum().as_ivec3();
+        let response = velocity.0*dir;
+        velocity.0 -= response; // Nullify the axis given
+
+        let inverse_dir = Vec3::ONE - dir;
+        mask.0 *= inverse_dir;
+    };
+
+    // First check individual axises
+    check_axis(Vec3::X);
+    check_axis(Vec3::Z);
+    check_axis(Vec3::Y);
+
+    // Check if there is any collision created only by the combination of the movement
+    check_axis(Vec3::X + Vec3::Z);
+    check_axis(Vec3::X + Vec3::Y);
+    check_axis(Vec3::Y + Vec3::Z);
+    //
+    // check_axis(Vec3::ONE);
+}
+
+/// Advances every body by one physics step: gravity is applied, then the body moves by its velocity along the axes free of blocks.
+/// Steps have a fixed duration and don't read the time, so that the same velocities always give the same movement,
+/// and so that bodies can be stepped by updating an `App` without any of the default plugins.
+pub fn step_bodies(
+    mut query: Query<(&mut VoxelBody, &mut Transform, &mut BoundingBox, &mut Velocity, &mut VelocityMask)>,
+    manager: Res<ChunkManager>,
+    registry: Res<BlockRegistry>,
+) {
+    for (mut body, mut transform, mut bounding, mut velocity, mut mask) in &mut query {
+        velocity.0.y = (velocity.0.y - body.gravity * STEP).max(-TERMINAL_VELOCITY);
+
+        let falling = velocity.0.y < 0.0;
+        collide(&bounding, &mut velocity, &mut mask, STEP, &manager, &registry);
+        body.on_ground = falling && mask.0.y == 0.0;
+
+        body.previous = transform.translation;
+        transform.translation += velocity.0 * mask.0 * STEP;
+        bounding.center = transform.translation;
+    }
+}
+
+/// Moves the entities following a body to their offset from it.
+/// Frames don't line up with physics steps, so followers are placed between the last two positions of the body,
+/// as far as the frame is into the next step. Without a physics timestep, they are placed on the body.
+pub fn follow_bodies(
+    mut followers: Query<(&FollowBody, &mut Transform), Without<VoxelBody>>,
+    bodies: Query<(&VoxelBody, &Transform)>,
+    timesteps: Option<Res<FixedTimesteps>>,
+) {
+    let overstep = timesteps
+        .as_ref()
+        .and_then(|t| t.get(PHYSICS_TIMESTEP))
+        .map_or(1.0, |state| state.overstep_percentage() as f32);
+
+    for (follow, mut transform) in &mut followers {
+        if let Ok((body, body_transform)) = bodies.get(follow.body) {
+            transform.translation = body.previous.lerp(body_transform.translation, overstep) + follow.offset;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        block::Block,
+        manager::{ChunkData, CHUNK_SIZE},
+        storage::PaletteStorage,
+    };
+
+    /// @returns A chunk of air with a floor of stone at y = 0
+    fn floor(stone: Block) -> ChunkManager {
+        let mut chunk = ChunkData::from_storage(PaletteStorage::Single(Block::AIR));
+        for (x, z) in ChunkData::slice() {
+            chunk.set_unchecked(IVec3::new(x as i32, 0, z as i32), stone);
+        }
+        chunk.generated = true;
+
+        let mut manager = ChunkManager::default();
+        manager.chunks.insert(IVec3::ZERO, chunk);
+        manager
+    }
+
+    #[test]
+    fn bodies_fall_and_rest_on_the_floor() {
+        let registry = BlockRegistry::load("assets/blocks.ron").unwrap();
+        let manager = floor(registry.by_name("stone").unwrap());
+
+        let mut app = App::new();
+        app.insert_resource(manager).insert_resource(registry).add_system(step_bodies);
+
+        let size = Vec3::new(0.8, 1.8, 0.8);
+        let middle = CHUNK_SIZE as f32 / 2.0;
+        let first = app.world.spawn(VoxelBodyBundle::new(Vec3::new(middle, 5.0, middle), size)).id();
+        let second = app.world.spawn(VoxelBodyBundle::new(Vec3::new(3.5, 12.0, 3.5), size)).id();
+
+        // Enough steps for both bodies to fall at their terminal velocity
+        for _ in 0..2 * STEPS_PER_SECOND as usize {
+            app.update();
+        }
+
+        for (entity, start) in [(first, 5.0), (second, 12.0)] {
+            let body = app.world.get::<VoxelBody>(entity).unwrap();
+            let transform = app.world.get::<Transform>(entity).unwrap();
+            let bounding = app.world.get::<BoundingBox>(entity).unwrap();
+
+            assert!(transform.translation.y < start, "body {entity:?} didn't move");
+            assert!(body.on_ground, "body {entity:?} isn't on the ground");
+            assert_eq!(bounding.center, transform.translation);
+
+            // Bodies stop before moving into the floor, at most one step above it
+            let bottom = transform.translation.y - size.y / 2.0;
+            assert!((1.0..1.0 + TERMINAL_VELOCITY * STEP).contains(&bottom), "body {entity:?} rests at {bottom}");
+        }
+    }
+
+    #[test]
+    fn bodies_dont_fall_into_chunks_being_generated() {
+        let registry = BlockRegistry::load("assets/blocks.ron").unwrap();
+        let mut manager = floor(registry.by_name("stone").unwrap());
+        // The chunk under the floor is loaded, but its terrain isn't generated yet
+        manager.chunks.insert(IVec3::NEG_Y, ChunkData::default());
+        manager.chunks.get_mut(&IVec3::ZERO).unwrap().set_unchecked(IVec3::new(8, 0, 8), Block::AIR);
+
+        let mut app = App::new();
+        app.insert_resource(manager).insert_resource(registry).add_system(step_bodies);
+        let start = Vec3::new(8.5, 1.0, 8.5);
+        let body = app.world.spawn(VoxelBodyBundle::new(start, Vec3::splat(0.8))).id();
+
+        for _ in 0..STEPS_PER_SECOND as usize {
+            app.update();
+        }
+
+        // The body falls through the hole in the floor and stops at the border of the chunk
+        let transform = app.world.get::<Transform>(body).unwrap();
+        assert!(transform.translation.y < start.y, "the body didn't fall through the hole");
+        assert!(transform.translation.y - 0.4 >= 0.0, "the body fell into the chunk at {}", transform.translation);
+        assert!(app.world.get::<VoxelBody>(body).unwrap().on_ground);
+    }
+}
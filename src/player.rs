@@ -1,13 +1,17 @@
-use std::f32::consts::PI;
+use std::{f32::consts::PI, marker::PhantomData};
 
-use bevy::{prelude::*, input::mouse::MouseMotion, window::CursorGrabMode};
+use bevy::{prelude::*, ecs::system::SystemParam, input::mouse::MouseMotion, window::CursorGrabMode};
 use bevy_inspector_egui::Inspectable;
 
-use crate::manager::ChunkManager;
+use crate::{manager::ChunkManager, biome::Biomes, block::{Block, BlockRegistry}, Noise};
 
 #[derive(Resource)]
 pub struct CameraDisabled(pub bool);
 
+/// Block placed with the right mouse button
+#[derive(Resource)]
+pub struct SelectedBlock(pub Block);
+
 #[derive(Inspectable, Component)]
 pub struct Velocity(pub Vec3);
 
@@ -42,6 +46,10 @@ impl BoundingBox {
         self.center + self.half_extents
     }
 
+    pub fn intersects(&self, other: &BoundingBox) -> bool {
+        self.min().cmplt(other.max()).all() && other.min().cmplt(self.max()).all()
+    }
+
     /// @returns An array of the bounding box's 8 corners
     pub fn points(&self) -> [Vec3; 8] {
         let c = self.center;
@@ -76,6 +84,13 @@ impl BoundingBox {
     }
 }
 
+/// Body controlled by the keyboard, followed by the camera
+#[derive(Component)]
+pub struct Player;
+
+/// Position of the camera relative to the center of the player's body
+pub const EYE_OFFSET: Vec3 = Vec3::new(0.0, 0.6, 0.0);
+
 pub fn rotate_camera(
     mut query: Query<&mut Transform, With<Camera>>,
     mut windows: ResMut<Windows>,
@@ -113,21 +128,18 @@ pub fn rotate_camera(
 
 }
 
-pub fn move_camera(
-    mut query: Query<(&mut Transform, &mut BoundingBox, &mut Velocity, &VelocityMask), With<Camera>>,
+/// Sets the velocity of the player from the keyboard, relative to where the camera looks.
+/// The physics steps then move the player, and the camera follows it.
+pub fn move_player(
+    mut query: Query<&mut Velocity, With<Player>>,
+    camera: Query<&Transform, With<Camera>>,
     keyboard: Res<Input<KeyCode>>,
-    time: Res<Time>
 ) {
-    let (mut camera, mut bounding, mut velocity, mask) = query.single_mut();
+    let mut velocity = query.single_mut();
+    let camera = camera.single();
 
-    camera.translation += velocity.0 * mask.0 * time.delta_seconds();
-    bounding.center = camera.translation - Vec3::new(0.0, 0.6, 0.0);
-
-    const ACCELERATION: f32 = 0.8;
     const SPEED: f32 = 8.0;
-    const GRAVITY: f32 = 0.5;
-    const TERMINAL_VELOCITY: f32 = 10.0;
-
+    const JUMP_VELOCITY: f32 = 10.0;
 
     let mut relative_offset = Vec3::ZERO;
 
@@ -149,59 +161,78 @@ pub fn move_camera(
     relative_offset = relative_offset.normalize_or_zero();
 
     let y = velocity.0.y;
-
     velocity.0 = relative_offset * SPEED;
-    // acceleration += Vec3::NEG_Y * GRAVITY;
-    velocity.0.y = y - GRAVITY;
-    velocity.0.y = velocity.0.y.max(-TERMINAL_VELOCITY);
-
-    // velocity.0 += acceleration/* *time.delta_seconds() */;
+    velocity.0.y = y;
 
     if keyboard.just_pressed(KeyCode::Space) {
-        velocity.0.y = 10.0;
+        velocity.0.y = JUMP_VELOCITY;
     }
 }
 
-pub fn collision(
-    mut query: Query<(&BoundingBox, &mut Velocity, &mut VelocityMask), With<Camera>>,
-    time: Res<Time>,
-    manager: Res<ChunkManager>
+/// Maximum distance at which blocks can be broken and placed
+const REACH: f32 = 6.0;
+
+/// Inputs the player interacts with the world through
+#[derive(SystemParam)]
+pub struct InteractInput<'w, 's> {
+    camera_disabled: Res<'w, CameraDisabled>,
+    mouse: Res<'w, Input<MouseButton>>,
+    keyboard: Res<'w, Input<KeyCode>>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+/// Breaks the targeted block with the left mouse button, and places the selected block against it with the right one.
+/// Number keys select the block to place.
+pub fn interact(
+    camera: Query<&Transform, With<Camera>>,
+    player: Query<&BoundingBox, With<Player>>,
+    mut manager: ResMut<ChunkManager>,
+    mut selected: ResMut<SelectedBlock>,
+    registry: Res<BlockRegistry>,
+    input: InteractInput,
 ) {
-    let (bounding, mut velocity, mut mask) = query.single_mut();
+    let InteractInput { camera_disabled, mouse, keyboard, .. } = input;
+    const KEYS: [KeyCode; 9] = [
+        KeyCode::Key1, KeyCode::Key2, KeyCode::Key3,
+        KeyCode::Key4, KeyCode::Key5, KeyCode::Key6,
+        KeyCode::Key7, KeyCode::Key8, KeyCode::Key9,
+    ];
+    for (id, key) in (1..).zip(KEYS) {
+        if keyboard.just_pressed(key) {
+            if let Some(info) = registry.try_get(Block(id)) {
+                selected.0 = Block(id);
+                info!("Selected {}", info.name);
+            }
+        }
+    }
 
-    mask.0 = Vec3::ONE;
+    if camera_disabled.0 {
+        return;
+    }
 
-    let mut check_axis = |dir: Vec3| {
-        if !bounding.points().into_iter().any(|point| {
-            let ( player_key, player_pos ) = ChunkManager::get_keys((point + velocity.0*dir*time.delta_seconds()).floor().as_ivec3());
-            
-            if let Some(c) = manager.chunks.get(&player_key) {
-                c.get_unchecked(player_pos).full()
-            }
-            else {
-                true
-            }
-        }){ return };
-
-        // let target = player_pos - (velocity.0*dir).signum().as_ivec3();
-        let response = velocity.0*dir;
-        velocity.0 -= response; // Nullify the axis given
-
-        let inverse_dir = Vec3::ONE - dir;
-        mask.0 *= inverse_dir;
-    };
-
-    // First check individual axises
-    check_axis(Vec3::X);
-    check_axis(Vec3::Z);
-    check_axis(Vec3::Y);
-
-    // Check if there is any collision created only by the combination of the movement
-    check_axis(Vec3::X + Vec3::Z);
-    check_axis(Vec3::X + Vec3::Y);
-    check_axis(Vec3::Y + Vec3::Z);
-    //
-    // check_axis(Vec3::ONE);
+    let (camera, bounding) = (camera.single(), player.single());
+    let Some(hit) = manager.raycast(&registry, camera.translation, camera.forward(), REACH) else { return };
+
+    if mouse.just_pressed(MouseButton::Left) {
+        manager.set_block(hit.pos, Block::AIR);
+    } else if mouse.just_pressed(MouseButton::Right) {
+        // Don't place full blocks inside of the player
+        let cell = BoundingBox::from_min_max(hit.previous.as_vec3(), hit.previous.as_vec3() + Vec3::ONE);
+        if registry.get(selected.0).full() && cell.intersects(bounding) {
+            return;
+        }
+        manager.set_block(hit.previous, selected.0);
+    }
 }
 
+/// Logs the biome the player is standing in when F4 is pressed
+pub fn report_biome(player: Query<&BoundingBox, With<Player>>, biomes: Res<Biomes>, noise: Res<Noise>, keyboard: Res<Input<KeyCode>>) {
+    if !keyboard.just_pressed(KeyCode::F4) {
+        return;
+    }
 
+    let Ok(bounding) = player.get_single() else { return };
+    let pos = bounding.center.floor().as_ivec3();
+    info!("Standing in the {:?} biome at {pos}", biomes.biome_at(&noise, pos.x, pos.z));
+}
@@ -0,0 +1,21 @@
+use std::path::Path;
+
+use crate::{
+    biome::Biomes,
+    Noise,
+};
+
+/// Height drawn in white, lower terrain fading to black
+const MAX_HEIGHT: f32 = 128.0;
+
+/// Renders the height of the terrain around the origin to a grayscale image, one pixel per column,
+/// so that the world generation config can be tuned without starting the game
+pub fn render_heightmap(noise: &Noise, biomes: &Biomes, size: u32, path: &Path) -> image::ImageResult<()> {
+    let half = size as i32 / 2;
+
+    let image = image::GrayImage::from_fn(size, size, |x, z| {
+        let (_, height) = biomes.column(noise, x as i32 - half, z as i32 - half);
+        image::Luma([(height as f32 / MAX_HEIGHT * 255.0).clamp(0.0, 255.0) as u8])
+    });
+    image.save(path)
+}
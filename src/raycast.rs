@@ -0,0 +1,223 @@
+use bevy::prelude::*;
+
+use crate::{block::{BlockRegistry, Face}, manager::ChunkManager};
+
+/// What a ray does when it reaches a chunk that isn't generated.
+/// The game only casts rays that stop for now, the other behaviours are there for the callers that need them.
+#[allow(unused)]
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Unloaded {
+    /// The ray stops without hitting anything
+    #[default]
+    Stop,
+    /// The ray hits the first block of the chunk, as if it was solid
+    Hit,
+    /// The ray goes through the chunk, as if it was empty
+    Skip,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RaycastHit {
+    /// Global position of the block that was hit
+    pub pos: IVec3,
+    /// Face of the block the ray entered through
+    pub face: Face,
+    /// Distance from the origin of the ray to the hit point
+    pub distance: f32,
+    /// Last empty cell crossed before the hit, where a block placed against the face would go
+    pub previous: IVec3,
+}
+
+impl ChunkManager {
+    /// Casts a ray through the world, stopping at non-generated chunks
+    /// @returns The first full block within `max_dist` of the origin, going through air and fluids
+    pub fn raycast(&self, registry: &BlockRegistry, origin: Vec3, dir: Vec3, max_dist: f32) -> Option<RaycastHit> {
+        self.raycast_with(registry, origin, dir, max_dist, Unloaded::Stop)
+    }
+
+    /// Amanatides & Woo voxel traversal, visiting every block the ray crosses in order.
+    /// Only full blocks are hit, and the block containing the origin is never hit.
+    pub fn raycast_with(&self, registry: &BlockRegistry, origin: Vec3, dir: Vec3, max_dist: f32, unloaded: Unloaded) -> Option<RaycastHit> {
+        let dir = dir.normalize_or_zero();
+        if dir == Vec3::ZERO {
+            return None;
+        }
+
+        let mut pos = origin.floor().as_ivec3();
+        let mut step = IVec3::ZERO;
+        // Distance along the ray to cross a whole block on each axis
+        let mut t_delta = Vec3::splat(f32::INFINITY);
+        // Distance along the ray to the next block boundary on each axis
+        let mut t_max = Vec3::splat(f32::INFINITY);
+
+        for axis in 0..3 {
+            if dir[axis] > 0.0 {
+                step[axis] = 1;
+                t_delta[axis] = 1.0 / dir[axis];
+                t_max[axis] = (pos[axis] as f32 + 1.0 - origin[axis]) / dir[axis];
+            } else if dir[axis] < 0.0 {
+                step[axis] = -1;
+                t_delta[axis] = -1.0 / dir[axis];
+                t_max[axis] = (origin[axis] - pos[axis] as f32) / -dir[axis];
+            }
+        }
+
+        loop {
+            let axis = if t_max.x < t_max.y {
+                if t_max.x < t_max.z { 0 } else { 2 }
+            } else if t_max.y < t_max.z {
+                1
+            } else {
+                2
+            };
+
+            let distance = t_max[axis];
+            if distance > max_dist {
+                return None;
+            }
+
+            let previous = pos;
+            pos[axis] += step[axis];
+            t_max[axis] += t_delta[axis];
+
+            let mut normal = IVec3::ZERO;
+            normal[axis] = -step[axis];
+            let face = Face::from_normal(normal).unwrap();
+            let hit = RaycastHit { pos, face, distance, previous };
+
+            let (key, local) = Self::get_keys(pos);
+            match self.chunks.get(&key).filter(|c| c.generated) {
+                Some(chunk) => {
+                    if registry.get(chunk.get_unchecked(local)).full() {
+                        return Some(hit);
+                    }
+                }
+                None => match unloaded {
+                    Unloaded::Stop => return None,
+                    Unloaded::Hit => return Some(hit),
+                    Unloaded::Skip => {}
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{block::Block, manager::ChunkData, storage::PaletteStorage};
+
+    /// @returns The block registry, and the stone block the worlds are built with
+    fn registry() -> (BlockRegistry, Block) {
+        let registry = BlockRegistry::load("assets/blocks.ron").unwrap();
+        let stone = registry.by_name("stone").unwrap();
+        (registry, stone)
+    }
+
+    /// @returns A world made of the given generated chunks filled with air
+    fn empty_world(keys: impl IntoIterator<Item = IVec3>) -> ChunkManager {
+        let mut manager = ChunkManager::default();
+        for key in keys {
+            let mut chunk = ChunkData::from_storage(PaletteStorage::Single(Block::AIR));
+            chunk.generated = true;
+            manager.chunks.insert(key, chunk);
+        }
+        manager
+    }
+
+    fn place(manager: &mut ChunkManager, pos: IVec3, block: Block) {
+        let (key, local) = ChunkManager::get_keys(pos);
+        manager.chunks.get_mut(&key).unwrap().set_unchecked(local, block);
+    }
+
+    fn assert_hit(hit: Option<RaycastHit>, pos: IVec3, face: Face, distance: f32, previous: IVec3) {
+        let hit = hit.expect("the ray didn't hit anything");
+        assert_eq!((hit.pos, hit.face, hit.previous), (pos, face, previous));
+        assert!((hit.distance - distance).abs() < 1e-4, "hit at distance {}, expected {distance}", hit.distance);
+    }
+
+    #[test]
+    fn axis_aligned_ray_skips_the_origin_block() {
+        let (registry, stone) = registry();
+        let mut manager = empty_world([IVec3::ZERO]);
+        place(&mut manager, IVec3::new(1, 2, 2), stone);
+        place(&mut manager, IVec3::new(5, 2, 2), stone);
+
+        let hit = manager.raycast(&registry, Vec3::new(1.5, 2.5, 2.5), Vec3::X, 10.0);
+        assert_hit(hit, IVec3::new(5, 2, 2), Face::WEST, 3.5, IVec3::new(4, 2, 2));
+
+        let hit = manager.raycast(&registry, Vec3::new(5.5, 8.5, 2.5), Vec3::NEG_Y, 10.0);
+        assert_hit(hit, IVec3::new(5, 2, 2), Face::TOP, 5.5, IVec3::new(5, 3, 2));
+    }
+
+    #[test]
+    fn diagonal_ray_visits_every_crossed_block() {
+        let (registry, stone) = registry();
+        let mut manager = empty_world([IVec3::ZERO]);
+        place(&mut manager, IVec3::new(2, 2, 0), stone);
+        // Next to the path of the ray, which goes through (1, 0, 0), (1, 1, 0) and (2, 1, 0) first
+        place(&mut manager, IVec3::new(0, 1, 0), stone);
+
+        let hit = manager.raycast(&registry, Vec3::new(0.5, 0.25, 0.5), Vec3::new(1.0, 1.0, 0.0), 10.0);
+        assert_hit(hit, IVec3::new(2, 2, 0), Face::BOTTOM, 1.75 * 2f32.sqrt(), IVec3::new(2, 1, 0));
+    }
+
+    #[test]
+    fn ray_crosses_into_negative_chunks() {
+        let (registry, stone) = registry();
+        let mut manager = empty_world([IVec3::ZERO, IVec3::NEG_X]);
+        place(&mut manager, IVec3::new(-3, 1, 1), stone);
+
+        let hit = manager.raycast(&registry, Vec3::new(2.5, 1.5, 1.5), Vec3::NEG_X, 10.0);
+        assert_hit(hit, IVec3::new(-3, 1, 1), Face::EAST, 4.5, IVec3::new(-2, 1, 1));
+    }
+
+    #[test]
+    fn ungenerated_chunks_stop_hit_or_let_the_ray_through() {
+        let (registry, stone) = registry();
+        // The chunk at x = 1 is loaded but not generated, and the one at x = 2 holds a block
+        let mut manager = empty_world([IVec3::ZERO, IVec3::new(2, 0, 0)]);
+        manager.chunks.insert(IVec3::X, ChunkData::default());
+        place(&mut manager, IVec3::new(34, 1, 1), stone);
+
+        let origin = Vec3::new(1.5, 1.5, 1.5);
+        assert_eq!(manager.raycast_with(&registry, origin, Vec3::X, 64.0, Unloaded::Stop), None);
+
+        let hit = manager.raycast_with(&registry, origin, Vec3::X, 64.0, Unloaded::Hit);
+        assert_hit(hit, IVec3::new(16, 1, 1), Face::WEST, 14.5, IVec3::new(15, 1, 1));
+
+        let hit = manager.raycast_with(&registry, origin, Vec3::X, 64.0, Unloaded::Skip);
+        assert_hit(hit, IVec3::new(34, 1, 1), Face::WEST, 32.5, IVec3::new(33, 1, 1));
+    }
+
+    #[test]
+    fn rays_go_through_fluids() {
+        let (registry, stone) = registry();
+        let mut manager = empty_world([IVec3::ZERO]);
+        let water = registry.by_name("water").unwrap();
+        for y in 2..6 {
+            place(&mut manager, IVec3::new(3, y, 3), water);
+        }
+        place(&mut manager, IVec3::new(3, 1, 3), stone);
+
+        // Looking down into water hits the block under it, and blocks placed against it replace the water
+        let hit = manager.raycast(&registry, Vec3::new(3.5, 8.5, 3.5), Vec3::NEG_Y, 10.0);
+        assert_hit(hit, IVec3::new(3, 1, 3), Face::TOP, 6.5, IVec3::new(3, 2, 3));
+
+        // Rays starting under water hit blocks too
+        let hit = manager.raycast(&registry, Vec3::new(3.5, 4.5, 3.5), Vec3::NEG_Y, 10.0);
+        assert_hit(hit, IVec3::new(3, 1, 3), Face::TOP, 2.5, IVec3::new(3, 2, 3));
+    }
+
+    #[test]
+    fn zero_direction_and_max_distance_stop_the_ray() {
+        let (registry, stone) = registry();
+        let mut manager = empty_world([IVec3::ZERO]);
+        place(&mut manager, IVec3::new(5, 2, 2), stone);
+        let origin = Vec3::new(1.5, 2.5, 2.5);
+
+        assert_eq!(manager.raycast(&registry, Vec3::new(4.5, 2.5, 2.5), Vec3::ZERO, 10.0), None);
+        assert_eq!(manager.raycast(&registry, origin, Vec3::X, 3.0), None);
+        assert_hit(manager.raycast(&registry, origin, Vec3::X, 3.5), IVec3::new(5, 2, 2), Face::WEST, 3.5, IVec3::new(4, 2, 2));
+    }
+}
@@ -0,0 +1,608 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+};
+
+use bevy::{prelude::*, utils::HashMap, app::AppExit};
+
+use crate::{
+    block::{Block, BlockRegistry},
+    light::SkyColumns,
+    manager::{ChunkData, ChunkManager, CHUNK_SIZE, CHUNK_VOLUME},
+    storage::PaletteStorage,
+    structure::PendingStructures,
+    tick::WorldTick,
+};
+
+/// Number of chunks per axis grouped in a region file
+pub const REGION_SIZE: i32 = 8;
+const REGION_VOLUME: usize = (REGION_SIZE * REGION_SIZE * REGION_SIZE) as usize;
+
+const REGION_MAGIC: &[u8; 4] = b"VXRG";
+const PENDING_MAGIC: &[u8; 4] = b"VXPB";
+/// Version of the file holding the blocks structures placed into neighbouring chunks
+pub const PENDING_VERSION: u32 = 1;
+/// Version of the region container layout
+pub const REGION_VERSION: u32 = 1;
+/// Version of the serialized chunk data, written in front of every chunk
+pub const CHUNK_VERSION: u16 = 1;
+
+/// Size of the region header: magic, version and the offset table
+const HEADER_SIZE: u64 = 4 + 4 + REGION_VOLUME as u64 * 8;
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Location of the world save on disk.
+/// Chunks are grouped in region files of `REGION_SIZE`³ chunks, each file starting with a table of where every chunk is stored.
+#[derive(Resource)]
+pub struct WorldSave {
+    pub path: PathBuf,
+}
+
+impl WorldSave {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// @returns A tuple of the key of the region and the index of the chunk inside of it
+    pub fn region_keys(key: IVec3) -> (IVec3, usize) {
+        let region = IVec3::new(
+            key.x.div_euclid(REGION_SIZE),
+            key.y.div_euclid(REGION_SIZE),
+            key.z.div_euclid(REGION_SIZE),
+        );
+        let local = key - region * REGION_SIZE;
+        let index = (local.x + local.y * REGION_SIZE + local.z * REGION_SIZE * REGION_SIZE) as usize;
+        (region, index)
+    }
+
+    fn region_path(&self, region: IVec3) -> PathBuf {
+        self.path.join(format!("r.{}.{}.{}.bin", region.x, region.y, region.z))
+    }
+
+    fn read_header(file: &mut File) -> io::Result<()> {
+        let mut magic = [0; 4];
+        file.read_exact(&mut magic)?;
+        if &magic != REGION_MAGIC {
+            return Err(invalid("not a region file"));
+        }
+
+        let version = read_u32(file)?;
+        if version != REGION_VERSION {
+            return Err(invalid(format!("unsupported region version {version}")));
+        }
+        Ok(())
+    }
+
+    /// Reads a single chunk from its region file, its scheduled updates being due relative to the given tick.
+    /// Blocks missing from the registry are an error rather than a crash once the chunk is meshed.
+    /// @returns None if the chunk was never saved
+    pub fn load_chunk(&self, key: IVec3, tick: u64, registry: &BlockRegistry) -> io::Result<Option<ChunkData>> {
+        let (region, index) = Self::region_keys(key);
+
+        let mut file = match File::open(self.region_path(region)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        Self::read_header(&mut file)?;
+
+        file.seek(SeekFrom::Current(index as i64 * 8))?;
+        let offset = read_u32(&mut file)?;
+        let len = read_u32(&mut file)?;
+        if offset == 0 {
+            return Ok(None);
+        }
+
+        let mut buffer = vec![0; len as usize];
+        file.seek(SeekFrom::Start(offset as u64))?;
+        file.read_exact(&mut buffer)?;
+
+        read_chunk(&mut buffer.as_slice(), tick, registry).map(Some)
+    }
+
+    /// Reads every chunk stored in a region file
+    fn load_region(&self, region: IVec3) -> io::Result<Vec<Option<Vec<u8>>>> {
+        let mut chunks = vec![None; REGION_VOLUME];
+
+        let mut file = match File::open(self.region_path(region)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(chunks),
+            Err(e) => return Err(e),
+        };
+        Self::read_header(&mut file)?;
+
+        let mut table = Vec::with_capacity(REGION_VOLUME);
+        for _ in 0..REGION_VOLUME {
+            table.push((read_u32(&mut file)?, read_u32(&mut file)?));
+        }
+
+        for (chunk, (offset, len)) in chunks.iter_mut().zip(table) {
+            if offset == 0 {
+                continue;
+            }
+            let mut buffer = vec![0; len as usize];
+            file.seek(SeekFrom::Start(offset as u64))?;
+            file.read_exact(&mut buffer)?;
+            *chunk = Some(buffer);
+        }
+
+        Ok(chunks)
+    }
+
+    /// Writes the given chunks to disk, rewriting each region file they belong to once.
+    /// Scheduled updates are saved as the number of ticks left after the given tick.
+    pub fn save_chunks<'a>(&self, chunks: impl IntoIterator<Item = (IVec3, &'a ChunkData)>, tick: u64) -> io::Result<()> {
+        let mut regions: HashMap<IVec3, Vec<(usize, Vec<u8>)>> = HashMap::new();
+        for (key, chunk) in chunks {
+            let (region, index) = Self::region_keys(key);
+            let mut buffer = Vec::new();
+            write_chunk(chunk, tick, &mut buffer)?;
+            regions.entry(region).or_default().push((index, buffer));
+        }
+
+        if regions.is_empty() {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.path)?;
+
+        for (region, updated) in regions {
+            let mut chunks = self.load_region(region)?;
+            for (index, buffer) in updated {
+                chunks[index] = Some(buffer);
+            }
+
+            let mut out = Vec::new();
+            out.extend_from_slice(REGION_MAGIC);
+            out.extend_from_slice(&REGION_VERSION.to_le_bytes());
+
+            let mut offset = HEADER_SIZE as u32;
+            for chunk in &chunks {
+                let (o, len) = match chunk {
+                    Some(buffer) => (offset, buffer.len() as u32),
+                    None => (0, 0),
+                };
+                out.extend_from_slice(&o.to_le_bytes());
+                out.extend_from_slice(&len.to_le_bytes());
+                offset += len;
+            }
+            for buffer in chunks.iter().flatten() {
+                out.extend_from_slice(buffer);
+            }
+
+            // Write to a temporary file first so that a crash never leaves a half-written region
+            let path = self.region_path(region);
+            let temporary = path.with_extension("tmp");
+            fs::write(&temporary, out)?;
+            fs::rename(temporary, path)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl WorldSave {
+    fn pending_path(&self) -> PathBuf {
+        self.path.join("pending.bin")
+    }
+
+    /// Writes the blocks structures placed into neighbouring chunks, and the chunks whose structures were placed
+    pub fn save_pending(&self, pending: &PendingStructures) -> io::Result<()> {
+        let mut out = Vec::new();
+        out.extend_from_slice(PENDING_MAGIC);
+        out.extend_from_slice(&PENDING_VERSION.to_le_bytes());
+
+        out.extend_from_slice(&(pending.blocks.len() as u32).to_le_bytes());
+        for (key, blocks) in &pending.blocks {
+            write_key(*key, &mut out);
+            out.extend_from_slice(&(blocks.len() as u32).to_le_bytes());
+            for (pos, block) in blocks {
+                out.extend_from_slice(&[pos.x as u8, pos.y as u8, pos.z as u8]);
+                out.extend_from_slice(&block.0.to_le_bytes());
+            }
+        }
+
+        out.extend_from_slice(&(pending.placed.len() as u32).to_le_bytes());
+        for key in &pending.placed {
+            write_key(*key, &mut out);
+        }
+
+        fs::create_dir_all(&self.path)?;
+        let path = self.pending_path();
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, out)?;
+        fs::rename(temporary, path)
+    }
+
+    /// Writes every dirty chunk along with the pending blocks, so that blocks left by structures are neither lost
+    /// for the chunks that aren't saved, nor for the chunks that took them in.
+    /// Chunks are written first, the pending blocks of the saved chunks being dropped as they are now part of them.
+    pub fn save_world(&self, manager: &mut ChunkManager, tick: u64) -> io::Result<()> {
+        let dirty = manager.chunks.iter().filter(|(_, c)| c.dirty).map(|(&k, _)| k).collect::<Vec<_>>();
+        self.save_chunks(dirty.iter().map(|key| (*key, &manager.chunks[key])), tick)?;
+
+        for key in dirty {
+            manager.mark_saved(key);
+        }
+        self.save_pending(&manager.pending)
+    }
+
+    /// Reads the blocks structures placed into neighbouring chunks, and the chunks whose structures were placed
+    pub fn load_pending(&self, registry: &BlockRegistry) -> io::Result<PendingStructures> {
+        let mut pending = PendingStructures::default();
+
+        let buffer = match fs::read(self.pending_path()) {
+            Ok(buffer) => buffer,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(pending),
+            Err(e) => return Err(e),
+        };
+        let r = &mut buffer.as_slice();
+
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != PENDING_MAGIC {
+            return Err(invalid("not a pending blocks file"));
+        }
+        let version = read_u32(r)?;
+        if version != PENDING_VERSION {
+            return Err(invalid(format!("unsupported pending blocks version {version}")));
+        }
+
+        for _ in 0..read_u32(r)? {
+            let key = read_key(r)?;
+            let len = read_u32(r)?;
+
+            let mut blocks = Vec::with_capacity(len as usize);
+            for _ in 0..len {
+                let pos = IVec3::new(read_u8(r)? as i32, read_u8(r)? as i32, read_u8(r)? as i32);
+                if pos.cmpge(IVec3::splat(CHUNK_SIZE as i32)).any() {
+                    return Err(invalid("pending block outside of its chunk"));
+                }
+                blocks.push((pos, read_block(r, registry)?));
+            }
+            pending.blocks.insert(key, blocks);
+        }
+
+        for _ in 0..read_u32(r)? {
+            pending.placed.insert(read_key(r)?);
+        }
+
+        Ok(pending)
+    }
+}
+
+#[cfg(test)]
+impl WorldSave {
+    /// Empty save in the temporary directory, only used by the given test
+    pub fn temporary(test: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("bevy_voxel_game-{}-{test}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        Self::new(path)
+    }
+}
+
+fn read_u8(r: &mut impl Read) -> io::Result<u8> {
+    let mut b = [0; 1];
+    r.read_exact(&mut b)?;
+    Ok(b[0])
+}
+
+fn read_u16(r: &mut impl Read) -> io::Result<u16> {
+    let mut b = [0; 2];
+    r.read_exact(&mut b)?;
+    Ok(u16::from_le_bytes(b))
+}
+
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut b = [0; 4];
+    r.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+
+/// Reads a block id, which has to be in the registry
+fn read_block(r: &mut impl Read, registry: &BlockRegistry) -> io::Result<Block> {
+    let block = Block(read_u16(r)?);
+    match registry.try_get(block) {
+        Some(_) => Ok(block),
+        None => Err(invalid(format!("unknown block id {}, the save may come from a different block file", block.0))),
+    }
+}
+
+fn write_key(key: IVec3, out: &mut Vec<u8>) {
+    for c in key.to_array() {
+        out.extend_from_slice(&c.to_le_bytes());
+    }
+}
+
+fn read_key(r: &mut impl Read) -> io::Result<IVec3> {
+    Ok(IVec3::new(read_u32(r)? as i32, read_u32(r)? as i32, read_u32(r)? as i32))
+}
+
+fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut b = [0; 8];
+    r.read_exact(&mut b)?;
+    Ok(u64::from_le_bytes(b))
+}
+
+const TAG_SINGLE: u8 = 0;
+const TAG_PACKED: u8 = 1;
+
+const FLAG_SKY: u8 = 1;
+/// Only some columns are above the terrain, their mask following the flags
+const FLAG_SKY_COLUMNS: u8 = 2;
+
+pub fn write_chunk(chunk: &ChunkData, tick: u64, w: &mut impl Write) -> io::Result<()> {
+    w.write_all(&CHUNK_VERSION.to_le_bytes())?;
+    let flags = match chunk.sky {
+        SkyColumns::ALL => FLAG_SKY,
+        SkyColumns::NONE => 0,
+        _ => FLAG_SKY_COLUMNS,
+    };
+    w.write_all(&[flags])?;
+    if flags == FLAG_SKY_COLUMNS {
+        for word in chunk.sky.0 {
+            w.write_all(&word.to_le_bytes())?;
+        }
+    }
+
+    match chunk.blocks() {
+        PaletteStorage::Single(block) => {
+            w.write_all(&[TAG_SINGLE])?;
+            w.write_all(&block.0.to_le_bytes())?;
+        }
+        PaletteStorage::Packed { palette, bits, words } => {
+            w.write_all(&[TAG_PACKED, *bits as u8])?;
+            w.write_all(&(palette.len() as u16).to_le_bytes())?;
+            for block in palette {
+                w.write_all(&block.0.to_le_bytes())?;
+            }
+            w.write_all(&(words.len() as u32).to_le_bytes())?;
+            for word in words {
+                w.write_all(&word.to_le_bytes())?;
+            }
+        }
+    }
+
+    let ticks = chunk.ticks.iter().collect::<Vec<_>>();
+    w.write_all(&(ticks.len() as u32).to_le_bytes())?;
+    for (pos, due) in ticks {
+        w.write_all(&[pos.x as u8, pos.y as u8, pos.z as u8])?;
+        w.write_all(&(due.saturating_sub(tick) as u32).to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+pub fn read_chunk(r: &mut impl Read, tick: u64, registry: &BlockRegistry) -> io::Result<ChunkData> {
+    let version = read_u16(r)?;
+    if version != CHUNK_VERSION {
+        return Err(invalid(format!("unsupported chunk version {version}")));
+    }
+    let flags = read_u8(r)?;
+    let mut sky = if flags & FLAG_SKY != 0 { SkyColumns::ALL } else { SkyColumns::NONE };
+    if flags & FLAG_SKY_COLUMNS != 0 {
+        for word in &mut sky.0 {
+            *word = read_u64(r)?;
+        }
+    }
+
+    let blocks = match read_u8(r)? {
+        TAG_SINGLE => PaletteStorage::Single(read_block(r, registry)?),
+        TAG_PACKED => {
+            let bits = read_u8(r)? as u32;
+            if !(1..=16).contains(&bits) {
+                return Err(invalid(format!("invalid palette index width {bits}")));
+            }
+
+            let palette_len = read_u16(r)? as usize;
+            let palette = (0..palette_len)
+                .map(|_| read_block(r, registry))
+                .collect::<io::Result<Vec<_>>>()?;
+
+            let per_word = (u64::BITS / bits) as usize;
+            let word_len = read_u32(r)? as usize;
+            if word_len != CHUNK_VOLUME.div_ceil(per_word) {
+                return Err(invalid("chunk data has the wrong length"));
+            }
+            let words = (0..word_len)
+                .map(|_| read_u64(r))
+                .collect::<io::Result<Vec<_>>>()?;
+
+            let blocks = PaletteStorage::Packed { palette, bits, words };
+            if !blocks.is_valid(CHUNK_VOLUME) {
+                return Err(invalid("chunk data indexes outside of its palette"));
+            }
+            blocks
+        }
+        tag => return Err(invalid(format!("unknown chunk storage tag {tag}"))),
+    };
+
+    let mut chunk = ChunkData::from_storage(blocks);
+    chunk.generated = true;
+    chunk.sky = sky;
+
+    for _ in 0..read_u32(r)? {
+        let pos = IVec3::new(read_u8(r)? as i32, read_u8(r)? as i32, read_u8(r)? as i32);
+        if pos.cmpge(IVec3::splat(CHUNK_SIZE as i32)).any() {
+            return Err(invalid("scheduled update outside of its chunk"));
+        }
+        chunk.ticks.schedule(pos, tick + read_u32(r)? as u64);
+    }
+    Ok(chunk)
+}
+
+/// Writes every dirty chunk and the pending blocks still in memory when the game closes
+pub fn save_on_exit(
+    mut exit: EventReader<AppExit>,
+    mut manager: ResMut<ChunkManager>,
+    save: Res<WorldSave>,
+    tick: Res<WorldTick>,
+) {
+    if exit.iter().next().is_none() {
+        return;
+    }
+
+    if let Err(e) = save.save_world(&mut manager, tick.0) {
+        error!("Could not save the world: {e}");
+    }
+}
+
+/// Time between two saves of the whole world
+pub const AUTOSAVE_SECONDS: f64 = 60.0;
+
+/// Writes every dirty chunk and the pending blocks from time to time, so that a crash loses as little as possible
+pub fn autosave(mut manager: ResMut<ChunkManager>, save: Res<WorldSave>, tick: Res<WorldTick>) {
+    if let Err(e) = save.save_world(&mut manager, tick.0) {
+        error!("Could not save the world: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> BlockRegistry {
+        BlockRegistry::load("assets/blocks.ron").unwrap()
+    }
+
+    /// @returns A chunk of stone and sand under air, with some columns open to the sky and scheduled updates
+    fn sample_chunk(registry: &BlockRegistry) -> ChunkData {
+        let [stone, sand] = ["stone", "sand"].map(|name| registry.by_name(name).unwrap());
+
+        let mut chunk = ChunkData::default();
+        for (x, y, z) in ChunkData::all() {
+            let height = (x + z) / 2;
+            let block = if y < height { stone } else if y == height { sand } else { Block::AIR };
+            chunk.set_unchecked(IVec3::new(x as i32, y as i32, z as i32), block);
+        }
+        for (x, z) in ChunkData::slice().filter(|&(x, z)| x + z < 8) {
+            chunk.sky.set(x, z);
+        }
+        chunk.ticks.schedule(IVec3::new(1, 2, 3), 105);
+        chunk.ticks.schedule(IVec3::new(15, 0, 15), 130);
+        chunk.generated = true;
+        chunk
+    }
+
+    fn assert_same_blocks(a: &ChunkData, b: &ChunkData) {
+        assert!(a.all_blocks().eq(b.all_blocks()), "the blocks of the chunk changed");
+    }
+
+    #[test]
+    fn chunks_round_trip() {
+        let registry = registry();
+        let chunk = sample_chunk(&registry);
+
+        let mut buffer = Vec::new();
+        write_chunk(&chunk, 100, &mut buffer).unwrap();
+        let read = read_chunk(&mut buffer.as_slice(), 1000, &registry).unwrap();
+
+        assert_same_blocks(&read, &chunk);
+        assert_eq!(read.sky, chunk.sky);
+        assert!(read.generated && !read.dirty);
+        // Updates are due as many ticks after loading as they were left when saving
+        let mut ticks = read.ticks.iter().collect::<Vec<_>>();
+        ticks.sort_by_key(|&(_, due)| due);
+        assert_eq!(ticks, [(IVec3::new(1, 2, 3), 1005), (IVec3::new(15, 0, 15), 1030)]);
+
+        for (block, sky) in [(Block::AIR, SkyColumns::ALL), (registry.by_name("stone").unwrap(), SkyColumns::NONE)] {
+            let mut chunk = ChunkData::from_storage(PaletteStorage::Single(block));
+            chunk.sky = sky;
+
+            let mut buffer = Vec::new();
+            write_chunk(&chunk, 0, &mut buffer).unwrap();
+            let read = read_chunk(&mut buffer.as_slice(), 0, &registry).unwrap();
+            assert_eq!((read.single_block(), read.sky), (Some(block), sky));
+        }
+    }
+
+    #[test]
+    fn scheduled_ticks_keep_their_delays() {
+        let registry = registry();
+        let save = WorldSave::temporary("scheduled_ticks_keep_their_delays");
+
+        let mut chunk = ChunkData::from_storage(PaletteStorage::Single(Block::AIR));
+        chunk.generated = true;
+        let [a, b, c] = [IVec3::new(0, 0, 0), IVec3::new(4, 8, 15), IVec3::new(15, 15, 15)];
+        chunk.ticks.schedule(a, 250);
+        chunk.ticks.schedule(b, 203);
+        // Already overdue when saved
+        chunk.ticks.schedule(c, 150);
+        save.save_chunks([(IVec3::new(3, -2, 1), &chunk)], 200).unwrap();
+
+        let mut read = save.load_chunk(IVec3::new(3, -2, 1), 40, &registry).unwrap().unwrap();
+        let mut ticks = read.ticks.iter().collect::<Vec<_>>();
+        ticks.sort_by_key(|&(_, due)| due);
+        assert_eq!(ticks, [(c, 40), (b, 43), (a, 90)]);
+        assert_eq!(read.ticks.take_due(43), [c, b]);
+    }
+
+    #[test]
+    fn unknown_versions_and_blocks_are_rejected() {
+        let registry = registry();
+        let mut buffer = Vec::new();
+        write_chunk(&sample_chunk(&registry), 0, &mut buffer).unwrap();
+
+        let mut newer = buffer.clone();
+        newer[..2].copy_from_slice(&(CHUNK_VERSION + 1).to_le_bytes());
+        assert!(read_chunk(&mut newer.as_slice(), 0, &registry).is_err());
+
+        let mut unknown = Vec::new();
+        write_chunk(&ChunkData::from_storage(PaletteStorage::Single(Block(u16::MAX))), 0, &mut unknown).unwrap();
+        assert!(read_chunk(&mut unknown.as_slice(), 0, &registry).is_err());
+    }
+
+    #[test]
+    fn unknown_pending_blocks_are_rejected() {
+        let registry = registry();
+        let save = WorldSave::temporary("unknown_pending_blocks_are_rejected");
+
+        let mut pending = PendingStructures::default();
+        pending.blocks.insert(IVec3::ZERO, vec![(IVec3::ONE, Block(u16::MAX))]);
+        save.save_pending(&pending).unwrap();
+        let error = save.load_pending(&registry).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("unknown block id"), "{error}");
+    }
+
+    #[test]
+    fn saves_round_trip() {
+        let registry = registry();
+        let save = WorldSave::temporary("saves_round_trip");
+        let stone = registry.by_name("stone").unwrap();
+
+        // Chunks sharing a region file, and one in a region of negative coordinates
+        let mut manager = ChunkManager::default();
+        let keys = [IVec3::ZERO, IVec3::new(1, 0, 0), IVec3::new(-1, -9, 3)];
+        for key in keys {
+            let mut chunk = sample_chunk(&registry);
+            chunk.set_unchecked(IVec3::new(0, 15, 0), stone);
+            chunk.dirty = true;
+            manager.chunks.insert(key, chunk);
+        }
+        manager.pending.blocks.insert(IVec3::new(-4, 2, 7), vec![(IVec3::new(0, 1, 15), stone), (IVec3::new(3, 3, 3), Block::AIR)]);
+        // Pending blocks of a saved chunk are part of it
+        manager.pending.blocks.insert(keys[1], vec![(IVec3::ONE, stone)]);
+        manager.pending.placed.extend([IVec3::new(-3, 2, 7), IVec3::new(0, -1, 0)]);
+
+        save.save_world(&mut manager, 0).unwrap();
+        assert!(manager.chunks.values().all(|c| !c.dirty));
+        assert!(!manager.pending.blocks.contains_key(&keys[1]));
+
+        // Saving a chunk again keeps the others of its region
+        manager.chunks.get_mut(&IVec3::ZERO).unwrap().set_unchecked(IVec3::ZERO, Block::AIR);
+        save.save_chunks([(IVec3::ZERO, &manager.chunks[&IVec3::ZERO])], 0).unwrap();
+
+        for key in keys {
+            let read = save.load_chunk(key, 0, &registry).unwrap().unwrap_or_else(|| panic!("chunk {key} wasn't saved"));
+            assert_same_blocks(&read, &manager.chunks[&key]);
+        }
+        assert!(save.load_chunk(IVec3::new(2, 0, 0), 0, &registry).unwrap().is_none());
+        assert!(save.load_chunk(IVec3::new(100, 0, 0), 0, &registry).unwrap().is_none());
+
+        assert_eq!(save.load_pending(&registry).unwrap(), manager.pending);
+    }
+}
@@ -0,0 +1,218 @@
+use std::mem::size_of;
+
+use crate::block::Block;
+
+/// Compact storage for a fixed number of blocks.
+/// Blocks are stored as indices into a palette of the distinct blocks present, packed in as few bits as possible.
+/// Storage containing a single kind of block (like an all-air sky chunk) doesn't allocate anything.
+#[derive(Clone, Debug)]
+pub enum PaletteStorage {
+    Single(Block),
+    Packed {
+        palette: Vec<Block>,
+        /// Number of bits used by each index
+        bits: u32,
+        words: Vec<u64>,
+    },
+}
+
+impl Default for PaletteStorage {
+    fn default() -> Self {
+        PaletteStorage::Single(Block::AIR)
+    }
+}
+
+/// Number of bits needed to index a palette of the given length
+fn bits_for(len: usize) -> u32 {
+    (usize::BITS - (len.max(2) - 1).leading_zeros()).max(1)
+}
+
+fn word_count(len: usize, bits: u32) -> usize {
+    len.div_ceil((u64::BITS / bits) as usize)
+}
+
+impl PaletteStorage {
+    #[inline]
+    fn read(words: &[u64], bits: u32, index: usize) -> usize {
+        let per_word = (u64::BITS / bits) as usize;
+        let word = words[index / per_word];
+        let shift = (index % per_word) as u32 * bits;
+        ((word >> shift) & ((1 << bits) - 1)) as usize
+    }
+
+    #[inline]
+    fn write(words: &mut [u64], bits: u32, index: usize, value: usize) {
+        let per_word = (u64::BITS / bits) as usize;
+        let word = &mut words[index / per_word];
+        let shift = (index % per_word) as u32 * bits;
+        let mask = ((1u64 << bits) - 1) << shift;
+        *word = (*word & !mask) | ((value as u64) << shift);
+    }
+
+    pub fn get(&self, index: usize) -> Block {
+        match self {
+            PaletteStorage::Single(block) => *block,
+            PaletteStorage::Packed { palette, bits, words } => palette[Self::read(words, *bits, index)],
+        }
+    }
+
+    /// Sets the block at the given index, `len` is the total amount of blocks in the storage
+    pub fn set(&mut self, len: usize, index: usize, block: Block) {
+        if let PaletteStorage::Single(current) = *self {
+            if current == block {
+                return;
+            }
+
+            *self = PaletteStorage::Packed {
+                palette: vec![current],
+                bits: 1,
+                words: vec![0; word_count(len, 1)],
+            };
+        }
+
+        let PaletteStorage::Packed { palette, bits, words } = self else { unreachable!() };
+
+        let value = match palette.iter().position(|&b| b == block) {
+            Some(value) => value,
+            None => {
+                palette.push(block);
+
+                // Repack every index with a larger width if the palette doesn't fit anymore
+                let needed = bits_for(palette.len());
+                if needed > *bits {
+                    let mut repacked = vec![0; word_count(len, needed)];
+                    for i in 0..len {
+                        Self::write(&mut repacked, needed, i, Self::read(words, *bits, i));
+                    }
+                    *words = repacked;
+                    *bits = needed;
+                }
+
+                palette.len() - 1
+            }
+        };
+
+        Self::write(words, *bits, index, value);
+    }
+
+    /// Removes unused palette entries, and collapses the storage if it only contains one block
+    pub fn compact(&mut self, len: usize) {
+        let PaletteStorage::Packed { palette, bits, words } = self else { return };
+
+        let mut used = vec![false; palette.len()];
+        for i in 0..len {
+            used[Self::read(words, *bits, i)] = true;
+        }
+
+        if used.iter().filter(|&&u| u).count() == 1 {
+            let index = used.iter().position(|&u| u).unwrap();
+            *self = PaletteStorage::Single(palette[index]);
+            return;
+        }
+
+        if used.iter().all(|&u| u) {
+            return;
+        }
+
+        // Map old palette indices to the new ones
+        let mut remap = vec![0; palette.len()];
+        let mut new_palette = Vec::new();
+        for (old, &block) in palette.iter().enumerate() {
+            if used[old] {
+                remap[old] = new_palette.len();
+                new_palette.push(block);
+            }
+        }
+
+        let new_bits = bits_for(new_palette.len());
+        let mut new_words = vec![0; word_count(len, new_bits)];
+        for i in 0..len {
+            Self::write(&mut new_words, new_bits, i, remap[Self::read(words, *bits, i)]);
+        }
+
+        *self = PaletteStorage::Packed {
+            palette: new_palette,
+            bits: new_bits,
+            words: new_words,
+        };
+    }
+
+    /// @returns Wether every stored index points inside the palette
+    pub fn is_valid(&self, len: usize) -> bool {
+        match self {
+            PaletteStorage::Single(_) => true,
+            PaletteStorage::Packed { palette, bits, words } => {
+                words.len() >= word_count(len, *bits)
+                    && (0..len).all(|i| Self::read(words, *bits, i) < palette.len())
+            }
+        }
+    }
+
+    /// @returns The number of bytes used by this storage, including its heap allocations
+    pub fn memory_usage(&self) -> usize {
+        size_of::<Self>()
+            + match self {
+                PaletteStorage::Single(_) => 0,
+                PaletteStorage::Packed { palette, words, .. } => {
+                    palette.capacity() * size_of::<Block>() + words.capacity() * size_of::<u64>()
+                }
+            }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+    use crate::manager::CHUNK_VOLUME;
+
+    fn assert_matches(storage: &PaletteStorage, expected: &[Block]) {
+        assert!(storage.is_valid(CHUNK_VOLUME), "an index points outside of the palette");
+        for (i, &block) in expected.iter().enumerate() {
+            assert_eq!(storage.get(i), block, "wrong block at index {i}");
+        }
+    }
+
+    #[test]
+    fn storage_matches_a_plain_array() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut storage = PaletteStorage::default();
+        let mut expected = vec![Block::AIR; CHUNK_VOLUME];
+
+        // Each round allows more kinds of blocks, growing the palette past several index widths
+        for kinds in [2, 3, 5, 9, 17, 40] {
+            for _ in 0..CHUNK_VOLUME / 4 {
+                let index = rng.gen_range(0..CHUNK_VOLUME);
+                let block = Block(rng.gen_range(0..kinds));
+                storage.set(CHUNK_VOLUME, index, block);
+                expected[index] = block;
+            }
+            assert_matches(&storage, &expected);
+            let PaletteStorage::Packed { palette, bits, .. } = &storage else { panic!("storage wasn't unpacked") };
+            assert!(palette.len() <= 1 << bits, "palette of {} blocks with {bits} bit indices", palette.len());
+        }
+
+        // Only a few kinds of blocks are left, compacting shrinks the indices
+        for (i, block) in expected.iter_mut().enumerate() {
+            if *block != Block(1) {
+                *block = Block(i as u16 % 3 * 20);
+                storage.set(CHUNK_VOLUME, i, *block);
+            }
+        }
+        storage.compact(CHUNK_VOLUME);
+        assert_matches(&storage, &expected);
+        let PaletteStorage::Packed { palette, bits, .. } = &storage else { panic!("storage collapsed with 4 blocks left") };
+        assert_eq!((palette.len(), *bits), (4, 2));
+
+        // A single kind of block collapses the storage back
+        for (i, block) in expected.iter_mut().enumerate() {
+            *block = Block(20);
+            storage.set(CHUNK_VOLUME, i, *block);
+        }
+        assert_matches(&storage, &expected);
+        storage.compact(CHUNK_VOLUME);
+        assert!(matches!(storage, PaletteStorage::Single(Block(20))));
+        assert_eq!(storage.memory_usage(), size_of::<PaletteStorage>());
+    }
+}
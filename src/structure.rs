@@ -0,0 +1,189 @@
+use bevy::{prelude::*, utils::{HashMap, HashSet}};
+use itertools::Itertools;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    biome::Biomes,
+    block::{Block, BlockRegistry, RegistryError},
+    manager::{ChunkData, ChunkManager, CHUNK_SIZE},
+    terrain::chunk_rng,
+    Noise,
+};
+
+/// Blocks used by structures
+#[derive(Resource, Clone, Copy)]
+pub struct StructureBlocks {
+    pub log: Block,
+    pub leaves: Block,
+    pub stone: Block,
+}
+
+impl StructureBlocks {
+    pub fn from_registry(registry: &BlockRegistry) -> Result<Self, RegistryError> {
+        Ok(Self {
+            log: registry.by_name("log")?,
+            leaves: registry.by_name("leaves")?,
+            stone: registry.by_name("stone")?,
+        })
+    }
+}
+
+/// Ordering used to resolve overlapping structures, air always losing.
+/// Taking the maximum is commutative, so the result doesn't depend on the order chunks are generated in.
+fn precedence(registry: &BlockRegistry, block: Block) -> (u16, u16) {
+    if block == Block::AIR {
+        (0, 0)
+    } else {
+        (1 + registry.get(block).structure_priority as u16, block.0)
+    }
+}
+
+/// @returns The block kept when a structure places `new` over `existing`
+pub fn merge(registry: &BlockRegistry, existing: Block, new: Block) -> Block {
+    if precedence(registry, new) > precedence(registry, existing) {
+        new
+    } else {
+        existing
+    }
+}
+
+/// Salt of the random generator of structures, see `chunk_rng`
+const STRUCTURE_SALT: u64 = 2;
+
+fn tree(rng: &mut StdRng, blocks: &StructureBlocks, base: IVec3, out: &mut Vec<(IVec3, Block)>) {
+    let height = rng.gen_range(4..=6);
+
+    // Leaves around the top of the trunk, with the corners trimmed
+    let top = base + IVec3::Y * height;
+    for ((x, y), z) in (-2..=2i32).cartesian_product(-2..=1).cartesian_product(-2..=2i32) {
+        let radius = if y >= 0 { 1 } else { 2 };
+        if x.abs() > radius || z.abs() > radius || (x.abs() == radius && z.abs() == radius && rng.gen_bool(0.5)) {
+            continue;
+        }
+        out.push((top + IVec3::new(x, y, z), blocks.leaves));
+    }
+
+    for y in 0..height {
+        out.push((base + IVec3::Y * y, blocks.log));
+    }
+}
+
+fn boulder(rng: &mut StdRng, blocks: &StructureBlocks, base: IVec3, out: &mut Vec<(IVec3, Block)>) {
+    let radius = rng.gen_range(1.0..2.5f32);
+    let r = radius.ceil() as i32;
+
+    for ((x, y), z) in (-r..=r).cartesian_product(-r..=r).cartesian_product(-r..=r) {
+        let offset = IVec3::new(x, y, z);
+        if offset.as_vec3().length() <= radius {
+            out.push((base + offset, blocks.stone));
+        }
+    }
+}
+
+/// Maximum number of structures of each kind in a chunk column
+pub const MAX_STRUCTURES: u32 = 4;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Structure {
+    Tree,
+    Boulder,
+}
+
+/// Generates the structures whose origin lies in the given chunk.
+/// Structures may overflow into the neighbouring chunks.
+/// @returns The global position of every block of the structures
+pub fn generate_structures(key: IVec3, noise: &Noise, biomes: &Biomes, blocks: &StructureBlocks) -> Vec<(IVec3, Block)> {
+    let mut out = Vec::new();
+    // The same for every chunk of the column
+    let mut rng = chunk_rng(noise, key * IVec3::new(1, 0, 1), STRUCTURE_SALT);
+    let origin = key * CHUNK_SIZE as i32;
+
+    let candidates = [Structure::Tree; MAX_STRUCTURES as usize]
+        .into_iter()
+        .enumerate()
+        .chain([Structure::Boulder; MAX_STRUCTURES as usize].into_iter().enumerate());
+
+    // Every chunk of the column draws the same candidates, and keeps the structures standing inside of it
+    for (i, structure) in candidates {
+        let x = origin.x + rng.gen_range(0..CHUNK_SIZE as i32);
+        let z = origin.z + rng.gen_range(0..CHUNK_SIZE as i32);
+        let mut structure_rng = StdRng::seed_from_u64(rng.gen());
+
+        let (biome, height) = biomes.column(noise, x, z);
+        let count = match structure {
+            Structure::Tree => biome.trees,
+            Structure::Boulder => biome.boulders,
+        };
+        let base = IVec3::new(x, height + 1, z);
+        if i as u32 >= count || base.y.div_euclid(CHUNK_SIZE as i32) != key.y {
+            continue;
+        }
+
+        match structure {
+            Structure::Tree => tree(&mut structure_rng, blocks, base, &mut out),
+            Structure::Boulder => boulder(&mut structure_rng, blocks, base, &mut out),
+        }
+    }
+
+    out
+}
+
+/// Blocks that structures placed into neighbouring chunks, so that generating those chunks again gives them back
+#[derive(Default, Clone, PartialEq, Eq, Debug)]
+pub struct PendingStructures {
+    /// Blocks placed by the structures of neighbouring chunks, in local coordinates, kept until their chunk is saved
+    pub blocks: HashMap<IVec3, Vec<(IVec3, Block)>>,
+    /// Chunks whose structures were already placed into their neighbours
+    pub placed: HashSet<IVec3>,
+}
+
+impl ChunkManager {
+    /// Applies the blocks left in the chunk `key` by the structures of its neighbours
+    pub fn apply_pending(&self, registry: &BlockRegistry, key: IVec3, data: &mut ChunkData) {
+        let Some(pending) = self.pending.blocks.get(&key) else { return };
+
+        for &(pos, block) in pending {
+            data.set_unchecked(pos, merge(registry, data.get_unchecked(pos), block));
+        }
+        data.compact();
+    }
+
+    /// Places the blocks of structures generated by the chunk `key` into `data`, and into its neighbours the first time only.
+    /// Blocks reaching into neighbours are kept as pending blocks, which are placed when the neighbour is generated or loaded.
+    /// Generating a chunk again then gives back the same blocks without putting its structures back over what changed since.
+    pub fn place_structures(&mut self, registry: &BlockRegistry, key: IVec3, data: &mut ChunkData, structures: Vec<(IVec3, Block)>) {
+        self.apply_pending(registry, key, data);
+        let placed = self.pending.placed.contains(&key);
+
+        for (global_pos, block) in structures {
+            let (target, pos) = ChunkManager::get_keys(global_pos);
+
+            if target == key {
+                data.set_unchecked(pos, merge(registry, data.get_unchecked(pos), block));
+                continue;
+            }
+            if placed {
+                continue;
+            }
+
+            self.pending.placed.insert(key);
+            self.pending.blocks.entry(target).or_default().push((pos, block));
+            if let Some(existing) = self.get_block(global_pos) {
+                let merged = merge(registry, existing, block);
+                if merged != existing {
+                    self.replace_block(global_pos, merged);
+                }
+            }
+        }
+
+        data.compact();
+    }
+
+    /// Records that the chunk `key` was written to disk, its pending blocks being saved along with it
+    pub fn mark_saved(&mut self, key: IVec3) {
+        if let Some(chunk) = self.chunks.get_mut(&key) {
+            chunk.dirty = false;
+        }
+        self.pending.blocks.remove(&key);
+    }
+}
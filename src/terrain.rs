@@ -0,0 +1,429 @@
+use bevy::{math::DVec3, prelude::*};
+use itertools::Itertools;
+use noise::{NoiseFn, Seedable};
+use rand::{rngs::StdRng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    biome::Biomes,
+    block::{Block, BlockRegistry, RegistryError},
+    light::SkyColumns,
+    manager::{ChunkData, CHUNK_SIZE},
+    storage::PaletteStorage,
+    Noise,
+};
+
+/// Blocks used by the terrain generator
+#[derive(Resource, Clone, Copy)]
+pub struct TerrainBlocks {
+    pub stone: Block,
+}
+
+impl TerrainBlocks {
+    pub fn from_registry(registry: &BlockRegistry) -> Result<Self, RegistryError> {
+        Ok(Self {
+            stone: registry.by_name("stone")?,
+        })
+    }
+}
+
+/// Shape of the caves carved into the terrain
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct CaveSettings {
+    /// Scale in blocks of the large open caverns
+    pub cheese_scale: f64,
+    /// Noise value above which caverns are carved, higher means fewer caverns
+    pub cheese_threshold: f64,
+    /// Scale in blocks of the winding tunnels
+    pub spaghetti_scale: f64,
+    /// Half width of the tunnels in noise space, higher means wider tunnels
+    pub spaghetti_width: f64,
+    /// Number of blocks under the surface that are never carved
+    pub min_depth: i32,
+    /// Height under which no cave is carved, the terrain being solid stone below it
+    pub min_height: i32,
+}
+
+impl Default for CaveSettings {
+    fn default() -> Self {
+        Self {
+            cheese_scale: 48.0,
+            cheese_threshold: 0.55,
+            spaghetti_scale: 64.0,
+            spaghetti_width: 0.06,
+            min_depth: 4,
+            min_height: -64,
+        }
+    }
+}
+
+/// Offsets applied to the sampled position so that each noise field is independent of the others
+const CHEESE_OFFSET: DVec3 = DVec3::new(1031.0, 0.0, -2719.0);
+const SPAGHETTI_OFFSET_A: DVec3 = DVec3::new(-4447.0, 151.0, 3181.0);
+const SPAGHETTI_OFFSET_B: DVec3 = DVec3::new(6229.0, -389.0, -5501.0);
+
+/// Random generator for a chunk, the same every time the chunk is generated.
+/// Each use passes its own salt, so that ores, structures and anything else drawing for the same chunk get unrelated numbers.
+pub fn chunk_rng(noise: &Noise, key: IVec3, salt: u64) -> StdRng {
+    let seed = (noise.0.seed() as u64) << 32;
+    let chunk = (key.x as u32 as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ (key.y as u32 as u64).wrapping_mul(0x1656_67B1_9E37_79F9)
+        ^ (key.z as u32 as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+    StdRng::seed_from_u64(seed ^ chunk ^ salt.wrapping_mul(0xD6E8_FEB8_6659_FD93))
+}
+
+/// @returns Wether the block at the given global position is carved out by a cave
+pub fn is_cave(noise: &Noise, caves: &CaveSettings, pos: IVec3, surface: i32) -> bool {
+    if pos.y > surface - caves.min_depth || pos.y < caves.min_height {
+        return false;
+    }
+
+    let pos = pos.as_dvec3();
+
+    // Cheese caves are the pockets where the noise is high
+    let cheese = noise.0.get((pos / caves.cheese_scale + CHEESE_OFFSET).to_array());
+    if cheese > caves.cheese_threshold {
+        return true;
+    }
+
+    // Spaghetti caves follow the intersection of the zero surfaces of two noise fields
+    let a = noise.0.get((pos / caves.spaghetti_scale + SPAGHETTI_OFFSET_A).to_array());
+    let b = noise.0.get((pos / caves.spaghetti_scale + SPAGHETTI_OFFSET_B).to_array());
+    a.abs() < caves.spaghetti_width && b.abs() < caves.spaghetti_width
+}
+
+/// Distance in blocks between the samples bounding the cave noise of a chunk, see `may_have_caves`
+const CAVE_SAMPLE_STEP: i32 = 4;
+/// Upper bound of how much the noise changes per unit of distance in noise space, measured with a margin
+const NOISE_SLOPE: f64 = 2.0;
+/// Upper bound of the small jumps the noise makes between lattice cells
+const NOISE_JUMP: f64 = 0.02;
+
+/// @returns Wether `is_cave` may carve a block of the chunk, from a coarse grid of samples of the cave noise.
+/// Between samples the noise changes by less than its slope times the distance to the sample, plus a jump.
+fn may_have_caves(noise: &Noise, caves: &CaveSettings, origin: IVec3) -> bool {
+    let half = (CAVE_SAMPLE_STEP - 1) as f64 / 2.0;
+    let margin = |scale: f64| NOISE_SLOPE * half * 3f64.sqrt() / scale + NOISE_JUMP;
+    let cells = 0..CHUNK_SIZE as i32 / CAVE_SAMPLE_STEP;
+
+    cells.clone().cartesian_product(cells.clone()).cartesian_product(cells).any(|((x, y), z)| {
+        let center = (origin + IVec3::new(x, y, z) * CAVE_SAMPLE_STEP).as_dvec3() + half;
+        let sample = |scale: f64, offset: DVec3| noise.0.get((center / scale + offset).to_array());
+
+        let cheese = sample(caves.cheese_scale, CHEESE_OFFSET) + margin(caves.cheese_scale) > caves.cheese_threshold;
+        let tunnel = |offset| sample(caves.spaghetti_scale, offset).abs() - margin(caves.spaghetti_scale) < caves.spaghetti_width;
+        cheese || (tunnel(SPAGHETTI_OFFSET_A) && tunnel(SPAGHETTI_OFFSET_B))
+    })
+}
+
+/// Generates the blocks of a chunk.
+/// Every block only depends on its global position, so that chunks line up no matter the order they are generated in.
+pub fn generate_chunk(key: IVec3, noise: &Noise, caves: &CaveSettings, biomes: &Biomes, blocks: &TerrainBlocks) -> ChunkData {
+    let origin = key * CHUNK_SIZE as i32;
+    let top = origin.y + CHUNK_SIZE as i32 - 1;
+
+    let columns = ChunkData::slice()
+        .map(|(x, z)| biomes.column(noise, origin.x + x as i32, origin.z + z as i32))
+        .collect_vec();
+
+    // Chunks in the sky or deep underground are made of a single block, and don't need to be filled block by block
+    let single = if columns.iter().all(|&(_, height)| origin.y > height) {
+        Some(Block::AIR)
+    } else if columns.iter().all(|&(biome, height)| top <= height - biome.filler_depth)
+        && (top < caves.min_height || !may_have_caves(noise, caves, origin))
+    {
+        Some(blocks.stone)
+    } else {
+        None
+    };
+    if let Some(block) = single {
+        let mut data = ChunkData::from_storage(PaletteStorage::Single(block));
+        if block == Block::AIR {
+            data.sky = SkyColumns::ALL;
+        }
+        data.generated = true;
+        return data;
+    }
+
+    let mut data = ChunkData::default();
+    for ((x, z), &(biome, height)) in ChunkData::slice().zip(&columns) {
+        if height < top {
+            data.sky.set(x, z);
+        }
+        let (x, z) = (x as i32, z as i32);
+
+        for y in 0..CHUNK_SIZE as i32 {
+            let pos = origin + IVec3::new(x, y, z);
+            let block = if pos.y > height {
+                Block::AIR
+            } else if pos.y == height {
+                biome.surface
+            } else if pos.y > height - biome.filler_depth {
+                biome.filler
+            } else if is_cave(noise, caves, pos, height) {
+                Block::AIR
+            } else {
+                blocks.stone
+            };
+            data.set_unchecked(IVec3::new(x, y, z), block);
+        }
+    }
+
+    data.compact();
+    data.generated = true;
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::utils::HashMap;
+
+    use super::*;
+    use crate::{
+        manager::ChunkManager,
+        ore::{place_ores, OreTable},
+        region::WorldSave,
+        structure::{generate_structures, StructureBlocks},
+        test_util::generator,
+    };
+
+    /// Caves of the reference volume, sampling the noise fields directly instead of going through `is_cave`
+    fn reference_cave(noise: &Noise, caves: &CaveSettings, pos: IVec3, height: i32) -> bool {
+        let sample = |scale: f64, offset: DVec3| noise.0.get((pos.as_dvec3() / scale + offset).to_array());
+        let tunnel = |offset| sample(caves.spaghetti_scale, offset).abs() < caves.spaghetti_width;
+
+        (caves.min_height..=height - caves.min_depth).contains(&pos.y)
+            && (sample(caves.cheese_scale, CHEESE_OFFSET) > caves.cheese_threshold || (tunnel(SPAGHETTI_OFFSET_A) && tunnel(SPAGHETTI_OFFSET_B)))
+    }
+
+    #[test]
+    fn chunks_line_up_across_borders() {
+        let (noise, caves, biomes, blocks) = generator(7);
+        let size = CHUNK_SIZE as i32;
+
+        // Each chunk on both sides of the border between x = -1 and x = 0 is generated on its own,
+        // over enough of the border for caves to cross it
+        let chunks: HashMap<_, _> = (-1..=0)
+            .cartesian_product(-4..=3)
+            .cartesian_product(-4..=3)
+            .map(|((x, y), z)| IVec3::new(x, y, z))
+            .map(|key| (key, generate_chunk(key, &noise, &caves, &biomes, &blocks)))
+            .collect();
+
+        // The two layers of blocks on each side of the border, compared with a volume built from global positions only
+        let mut carved = 0;
+        for ((x, y), z) in (-2..2).cartesian_product(-4 * size..4 * size).cartesian_product(-4 * size..4 * size) {
+            let pos = IVec3::new(x, y, z);
+            let (biome, height) = biomes.column(&noise, x, z);
+            let expected = if y > height {
+                Block::AIR
+            } else if y == height {
+                biome.surface
+            } else if y > height - biome.filler_depth {
+                biome.filler
+            } else if reference_cave(&noise, &caves, pos, height) {
+                carved += 1;
+                Block::AIR
+            } else {
+                blocks.stone
+            };
+
+            let (key, local) = ChunkManager::get_keys(pos);
+            assert_eq!(chunks[&key].get_unchecked(local), expected, "block at {pos} doesn't match the reference");
+        }
+        assert!(carved > 0, "no cave reaches the border");
+    }
+
+    #[test]
+    fn buried_chunks_without_caves_are_single_blocks() {
+        let (noise, caves, biomes, blocks) = generator(7);
+        let size = CHUNK_SIZE as i32;
+
+        // Chunks under the surface, above the height caves stop at
+        let mut single = 0;
+        for ((x, y), z) in (-3..=3).cartesian_product(-4..=-1).cartesian_product(-3..=3) {
+            let key = IVec3::new(x, y, z);
+            let data = generate_chunk(key, &noise, &caves, &biomes, &blocks);
+            if data.single_block() != Some(blocks.stone) {
+                continue;
+            }
+            single += 1;
+
+            for (x, y, z) in ChunkData::all() {
+                let pos = key * size + IVec3::new(x as i32, y as i32, z as i32);
+                let (_, height) = biomes.column(&noise, pos.x, pos.z);
+                assert!(!reference_cave(&noise, &caves, pos, height), "chunk {key} is filled with stone over the cave at {pos}");
+            }
+        }
+        assert!(single > 0, "no buried chunk is a single block");
+    }
+
+    /// Everything needed to generate the terrain, ores and structures of chunks
+    struct Generator {
+        noise: Noise,
+        caves: CaveSettings,
+        biomes: Biomes,
+        blocks: TerrainBlocks,
+        registry: BlockRegistry,
+        ores: OreTable,
+        structure_blocks: StructureBlocks,
+    }
+
+    impl Generator {
+        fn new(seed: u32) -> Self {
+            let (noise, caves, biomes, blocks) = generator(seed);
+            let registry = BlockRegistry::load("assets/blocks.ron").unwrap();
+            let ores = OreTable::load("assets/ores.ron", &registry).unwrap();
+            let structure_blocks = StructureBlocks::from_registry(&registry).unwrap();
+            Self { noise, caves, biomes, blocks, registry, ores, structure_blocks }
+        }
+
+        /// Generates a chunk and places its structures, like `apply_terrain` does
+        fn generate(&self, manager: &mut ChunkManager, key: IVec3) {
+            let mut data = generate_chunk(key, &self.noise, &self.caves, &self.biomes, &self.blocks);
+            place_ores(key, &self.noise, &self.ores, &mut data);
+            let structures = generate_structures(key, &self.noise, &self.biomes, &self.structure_blocks);
+            manager.place_structures(&self.registry, key, &mut data, structures);
+            manager.chunks.insert(key, data);
+        }
+
+        /// Loads a chunk from the save, or generates it if it was never saved, like `process_load_queue` does
+        fn load(&self, manager: &mut ChunkManager, save: &WorldSave, key: IVec3) {
+            match save.load_chunk(key, 0, &self.registry).unwrap() {
+                Some(mut data) => {
+                    manager.apply_pending(&self.registry, key, &mut data);
+                    manager.chunks.insert(key, data);
+                }
+                None => self.generate(manager, key),
+            }
+        }
+    }
+
+    /// Columns of chunks going from underground to above the trees
+    fn tree_keys() -> Vec<IVec3> {
+        (-1..=1)
+            .cartesian_product(2..=5)
+            .cartesian_product(-1..=1)
+            .map(|((x, y), z)| IVec3::new(x, y, z))
+            .collect()
+    }
+
+    /// Generates the given chunks one after the other
+    fn generate_in_order(keys: impl IntoIterator<Item = IVec3>) -> ChunkManager {
+        let generator = Generator::new(7);
+        let mut manager = ChunkManager::default();
+        for key in keys {
+            generator.generate(&mut manager, key);
+        }
+        manager
+    }
+
+    #[test]
+    fn chunks_dont_depend_on_generation_order() {
+        let keys = tree_keys();
+        let forward = generate_in_order(keys.iter().copied());
+        let backward = generate_in_order(keys.iter().rev().copied());
+
+        // Structures were placed into neighbours, either directly or through pending blocks, without modifying them
+        assert!(!forward.pending.blocks.is_empty() && !backward.pending.blocks.is_empty(), "no structure crosses a chunk border");
+        assert!(forward.chunks.values().chain(backward.chunks.values()).all(|c| !c.dirty), "generated chunks are marked as modified");
+        for key in keys {
+            assert!(
+                forward.chunks[&key].all_blocks().eq(backward.chunks[&key].all_blocks()),
+                "chunk {key} depends on the order chunks are generated in"
+            );
+        }
+    }
+
+    /// @returns The key of a chunk and the position of a leaf of one of its trees, standing in a neighbour
+    fn cross_border_leaf(generator: &Generator, keys: &[IVec3]) -> (IVec3, IVec3) {
+        let leaves = generator.structure_blocks.leaves;
+        let mut manager = ChunkManager::default();
+        for &key in keys {
+            generator.generate(&mut manager, key);
+        }
+
+        keys.iter()
+            .find_map(|&key| {
+                generate_structures(key, &generator.noise, &generator.biomes, &generator.structure_blocks)
+                    .into_iter()
+                    .find(|&(pos, block)| block == leaves && ChunkManager::get_keys(pos).0 != key && manager.get_block(pos) == Some(leaves))
+                    .map(|(pos, _)| (key, pos))
+            })
+            .expect("no tree crosses a chunk border")
+    }
+
+    #[test]
+    fn broken_structures_stay_broken_once_reloaded() {
+        let generator = Generator::new(7);
+        let keys = tree_keys();
+        let (origin, leaf) = cross_border_leaf(&generator, &keys);
+
+        let mut manager = ChunkManager::default();
+        for &key in &keys {
+            generator.generate(&mut manager, key);
+        }
+        manager.set_block(leaf, Block::AIR);
+        let save = WorldSave::temporary("broken_structures_stay_broken_once_reloaded");
+        save.save_world(&mut manager, 0).unwrap();
+
+        // The chunk of the tree is unloaded and loaded back
+        manager.chunks.remove(&origin);
+        generator.load(&mut manager, &save, origin);
+        assert_eq!(manager.get_block(leaf), Some(Block::AIR), "the leaf at {leaf} grew back with the chunk {origin}");
+    }
+
+    #[test]
+    fn regenerated_chunks_get_their_structures_back() {
+        let generator = Generator::new(7);
+        let keys = tree_keys();
+        let (origin, leaf) = cross_border_leaf(&generator, &keys);
+        let (target, _) = ChunkManager::get_keys(leaf);
+
+        let mut manager = ChunkManager::default();
+        for &key in &keys {
+            generator.generate(&mut manager, key);
+        }
+        assert!(!manager.chunks[&target].dirty, "the leaf marked the chunk {target} as modified");
+
+        // The unmodified neighbour isn't saved when unloaded, and is generated again
+        let save = WorldSave::temporary("regenerated_chunks_get_their_structures_back");
+        save.save_world(&mut manager, 0).unwrap();
+        manager.chunks.remove(&target);
+        generator.load(&mut manager, &save, target);
+        assert_eq!(manager.get_block(leaf), Some(generator.structure_blocks.leaves), "the leaf at {leaf} of the chunk {origin} was lost");
+    }
+
+    #[test]
+    fn saved_chunks_drop_their_pending_blocks() {
+        let generator = Generator::new(7);
+        let keys = tree_keys();
+        let (origin, leaf) = cross_border_leaf(&generator, &keys);
+        let (target, _) = ChunkManager::get_keys(leaf);
+
+        // The neighbour the leaf goes into is saved and unloaded before the tree is generated
+        let mut manager = ChunkManager::default();
+        for &key in keys.iter().filter(|&&k| k != origin) {
+            generator.generate(&mut manager, key);
+        }
+        manager.chunks.get_mut(&target).unwrap().dirty = true;
+        let save = WorldSave::temporary("saved_chunks_drop_their_pending_blocks");
+        save.save_world(&mut manager, 0).unwrap();
+        manager.chunks.remove(&target);
+
+        generator.generate(&mut manager, origin);
+        assert!(manager.pending.blocks.contains_key(&target), "the leaf wasn't kept for the unloaded chunk");
+
+        // The leaf reaches the chunk loaded from disk, and is part of it once saved again
+        generator.load(&mut manager, &save, target);
+        assert_eq!(manager.get_block(leaf), Some(generator.structure_blocks.leaves), "the leaf at {leaf} didn't reach the saved chunk");
+        manager.chunks.get_mut(&target).unwrap().dirty = true;
+        save.save_world(&mut manager, 0).unwrap();
+        assert!(!manager.pending.blocks.contains_key(&target), "pending blocks of the saved chunk {target} are kept");
+
+        manager.chunks.remove(&target);
+        generator.load(&mut manager, &save, target);
+        assert_eq!(manager.get_block(leaf), Some(generator.structure_blocks.leaves), "the leaf at {leaf} wasn't saved with its chunk");
+    }
+}
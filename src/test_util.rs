@@ -0,0 +1,18 @@
+use noise::OpenSimplex;
+
+use crate::{
+    biome::Biomes,
+    block::BlockRegistry,
+    config::WorldGenConfig,
+    terrain::{CaveSettings, TerrainBlocks},
+    Noise,
+};
+
+/// Everything needed to generate chunks of the default config with the given seed
+pub fn generator(seed: u32) -> (Noise, CaveSettings, Biomes, TerrainBlocks) {
+    let registry = BlockRegistry::load("assets/blocks.ron").unwrap();
+    let config = WorldGenConfig::default();
+    let biomes = Biomes::from_registry(&registry, config.heightmap.build(seed), &config.filler_depths).unwrap();
+    let blocks = TerrainBlocks::from_registry(&registry).unwrap();
+    (Noise(OpenSimplex::new(seed)), config.caves, biomes, blocks)
+}
@@ -0,0 +1,185 @@
+use std::collections::BTreeMap;
+
+use bevy::{prelude::*, utils::HashMap};
+use itertools::Itertools;
+use rand::{rngs::ThreadRng, Rng};
+
+use crate::{
+    block::{BlockRegistry, Face},
+    fluid::FluidTable,
+    manager::{ChunkManager, CHUNK_SIZE},
+};
+
+/// Number of world ticks per second
+pub const TICKS_PER_SECOND: f64 = 20.0;
+
+/// Number of blocks picked at random in every loaded chunk each tick, when not given on the command line
+pub const DEFAULT_RANDOM_TICKS: u32 = 3;
+
+/// Number of ticks since the game started.
+/// Saved queues store the delay left before each update rather than this, so it doesn't need to be saved.
+#[derive(Resource, Default)]
+pub struct WorldTick(pub u64);
+
+#[derive(Resource)]
+pub struct TickSettings {
+    /// Number of blocks picked at random in every loaded chunk each tick
+    pub random_ticks: u32,
+}
+
+/// Updates the blocks of a chunk asked for, in local coordinates
+#[derive(Default, Clone)]
+pub struct TickQueue {
+    scheduled: BTreeMap<u64, Vec<IVec3>>,
+    /// Tick each scheduled position is due at, so that a block isn't updated twice for changes happening together
+    pending: HashMap<IVec3, u64>,
+}
+
+impl TickQueue {
+    /// Schedules an update of the block at the given tick, unless an earlier one is already waiting
+    pub fn schedule(&mut self, pos: IVec3, due: u64) {
+        match self.pending.insert(pos, due) {
+            Some(previous) if previous <= due => {
+                self.pending.insert(pos, previous);
+                return;
+            }
+            Some(previous) => {
+                let positions = self.scheduled.get_mut(&previous).unwrap();
+                positions.retain(|&p| p != pos);
+                if positions.is_empty() {
+                    self.scheduled.remove(&previous);
+                }
+            }
+            None => (),
+        }
+        self.scheduled.entry(due).or_default().push(pos);
+    }
+
+    /// Removes the updates due at or before the given tick
+    /// @returns The positions of the blocks to update, the oldest updates first
+    pub fn take_due(&mut self, tick: u64) -> Vec<IVec3> {
+        let later = self.scheduled.split_off(&(tick + 1));
+        let due = std::mem::replace(&mut self.scheduled, later).into_values().flatten().collect_vec();
+        for pos in &due {
+            self.pending.remove(pos);
+        }
+        due
+    }
+
+    /// @returns An iterator over every scheduled update and the tick it is due at
+    pub fn iter(&self) -> impl Iterator<Item = (IVec3, u64)> + '_ {
+        self.scheduled.iter().flat_map(|(&due, positions)| positions.iter().map(move |&pos| (pos, due)))
+    }
+}
+
+/// Everything the behaviour of a block can read or change when it is ticked
+pub struct TickContext<'a> {
+    pub manager: &'a mut ChunkManager,
+    pub registry: &'a BlockRegistry,
+    pub fluids: &'a FluidTable,
+    pub rng: ThreadRng,
+    pub tick: u64,
+}
+
+impl TickContext<'_> {
+    /// Schedules an update of the block at the given global position, the given number of ticks from now
+    pub fn schedule(&mut self, pos: IVec3, delay: u64) {
+        self.manager.schedule_tick(pos, self.tick + delay.max(1));
+    }
+}
+
+/// Advances the world by one tick.
+/// Blocks next to the ones changed since the last tick schedule their update, the updates that are due run,
+/// then a few random blocks of every loaded chunk are ticked.
+pub fn tick_blocks(
+    mut manager: ResMut<ChunkManager>,
+    mut tick: ResMut<WorldTick>,
+    settings: Res<TickSettings>,
+    registry: Res<BlockRegistry>,
+    fluids: Res<FluidTable>,
+) {
+    tick.0 += 1;
+    let registry = &*registry;
+    let mut ctx = TickContext { manager: &mut manager, registry, fluids: &fluids, rng: rand::thread_rng(), tick: tick.0 };
+
+    for pos in std::mem::take(&mut ctx.manager.block_updates) {
+        for neighbour in Face::ALL.into_iter().map(|f| pos + f.normal()).chain([pos]) {
+            let Some(block) = ctx.manager.get_block(neighbour) else { continue };
+            if let Some(delay) = registry.get(block).update_delay(&ctx) {
+                ctx.schedule(neighbour, delay);
+            }
+        }
+    }
+
+    // Chunks out of range stay in memory for a while, frozen until the player comes back
+    let keys = ctx.manager.chunks
+        .iter()
+        .filter(|(_, c)| c.generated && c.cached_time.is_none())
+        .map(|(&k, _)| k)
+        .collect_vec();
+    let size = CHUNK_SIZE as i32;
+
+    let mut due = Vec::new();
+    for &key in &keys {
+        let chunk = ctx.manager.chunks.get_mut(&key).unwrap();
+        due.extend(chunk.ticks.take_due(ctx.tick).into_iter().map(|pos| key * size + pos));
+    }
+    for pos in due {
+        let Some(block) = ctx.manager.get_block(pos) else { continue };
+        registry.get(block).scheduled_tick(&mut ctx, pos);
+    }
+
+    for key in keys {
+        let Some(chunk) = ctx.manager.chunks.get(&key) else { continue };
+        // Most chunks are only made of air or stone
+        if chunk.single_block().is_some_and(|b| !registry.get(b).random_ticks()) {
+            continue;
+        }
+
+        for _ in 0..settings.random_ticks {
+            let pos = IVec3::new(ctx.rng.gen_range(0..size), ctx.rng.gen_range(0..size), ctx.rng.gen_range(0..size));
+            let Some(block) = ctx.manager.chunks.get(&key).map(|c| c.get_unchecked(pos)) else { break };
+            registry.get(block).random_tick(&mut ctx, key * size + pos);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_are_scheduled_once() {
+        let mut queue = TickQueue::default();
+        let [a, b] = [IVec3::ZERO, IVec3::ONE];
+        queue.schedule(a, 10);
+        queue.schedule(a, 20);
+        queue.schedule(b, 10);
+        queue.schedule(b, 5);
+
+        // Only the earliest update of each block is kept
+        let mut scheduled = queue.iter().collect::<Vec<_>>();
+        scheduled.sort_by_key(|&(_, due)| due);
+        assert_eq!(scheduled, [(b, 5), (a, 10)]);
+
+        assert_eq!(queue.take_due(20), [b, a]);
+        assert_eq!(queue.iter().count(), 0);
+        // Blocks can be scheduled again once updated
+        queue.schedule(a, 30);
+        assert_eq!(queue.iter().collect::<Vec<_>>(), [(a, 30)]);
+    }
+
+    #[test]
+    fn due_updates_come_in_order() {
+        let mut queue = TickQueue::default();
+        let positions = (0..6).map(|i| IVec3::new(i, 0, 0)).collect_vec();
+        for (pos, due) in positions.iter().zip([7, 3, 12, 3, 1, 9]) {
+            queue.schedule(*pos, due);
+        }
+
+        // Updates due at the same tick keep the order they were scheduled in
+        assert_eq!(queue.take_due(7), [positions[4], positions[1], positions[3], positions[0]]);
+        assert_eq!(queue.take_due(8), []);
+        assert_eq!(queue.take_due(100), [positions[5], positions[2]]);
+    }
+}